@@ -1,10 +1,34 @@
 use crate::api::Env;
-use crate::domain::models::{Report, ReportStatus};
+use crate::domain::models::{Principal, ReportRequest};
+use crate::domain::registry::RegistryError;
+use crate::executor::explain::Explain;
+use crate::executor::planner::{Error, QueryPlanner};
 
 use axum::extract::{Path, State};
-use axum::response::IntoResponse;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Header the authenticating gateway in front of the API names the caller
+/// in.
+const PRINCIPAL_HEADER: &str = "x-principal";
+/// Prefix of the headers carrying the attributes of the caller as comma
+/// separated values, e.g. `x-principal-advertisers: 1,2`.
+const ATTRIBUTE_PREFIX: &str = "x-principal-";
+/// Header carrying the hex encoded HMAC-SHA256 of the other principal
+/// headers, keyed with the principal secret, over one `name:value` line per
+/// header in name order.
+const SIGNATURE_HEADER: &str = "x-principal-signature";
+/// Signed header carrying the Unix time, in seconds, the signature expires
+/// at, so that captured headers cannot be replayed.
+const EXPIRES_HEADER: &str = "x-principal-expires";
+/// Longest a signature is accepted for, however late it claims to expire.
+const MAX_SIGNATURE_LIFETIME: u64 = 300;
 
 #[axum_macros::debug_handler]
 pub async fn root(State(_): State<Arc<Env>>) -> impl IntoResponse {
@@ -13,14 +37,14 @@ pub async fn root(State(_): State<Arc<Env>>) -> impl IntoResponse {
 
 #[axum_macros::debug_handler]
 pub async fn get_datasources(State(env): State<Arc<Env>>) -> impl IntoResponse {
-    Json(env.repository.load_datasources()).into_response()
+    Json(env.registry.datasources()).into_response()
 }
 
 #[axum_macros::debug_handler]
 pub async fn report(
-    Path(report_id): Path<String>,
+    Path(_report_id): Path<String>,
     State(_env): State<Arc<Env>>,
-) -> impl IntoResponse {
+) -> Response {
     // Json(Report::parse(
     //     report_id,
     //     vec!["id", "name"],
@@ -32,6 +56,283 @@ pub async fn report(
 }
 
 #[axum_macros::debug_handler]
-pub async fn query(State(_env): State<Arc<Env>>) -> impl IntoResponse {
+pub async fn query(State(_env): State<Arc<Env>>) -> Response {
     todo!("Not implemented");
 }
+
+#[axum_macros::debug_handler]
+pub async fn explain(
+    State(env): State<Arc<Env>>,
+    headers: HeaderMap,
+    Json(request): Json<ReportRequest>,
+) -> Response {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let principal =
+        match principal(&headers, env.principal_secret.as_deref(), now) {
+            Ok(principal) => principal,
+            Err(error) => return error.into_response(),
+        };
+    match plan_explain(&env, principal, request) {
+        Ok(explain) => Json(explain).into_response(),
+        Err(error) => error.into_response(),
+    }
+}
+
+/// Runs the planner outside of the handler future, which it cannot be
+/// held across.
+fn plan_explain(
+    env: &Env,
+    principal: Option<Principal>,
+    request: ReportRequest,
+) -> Result<Explain, (StatusCode, String)> {
+    let mut planner =
+        QueryPlanner::for_request(&env.registry, env.schema.clone(), &request)
+            .map_err(planner_error)?;
+    if let Some(principal) = principal {
+        planner = planner.with_principal(principal);
+    }
+    planner.explain(request).map_err(planner_error)
+}
+
+/// Caller named by the gateway, if any, once the signature of its headers
+/// is verified and has not expired at `now`, in Unix seconds. Repeated
+/// principal headers are rejected rather than merged, as merging would
+/// widen the values the caller is restricted to.
+fn principal(
+    headers: &HeaderMap,
+    secret: Option<&str>,
+    now: u64,
+) -> Result<Option<Principal>, (StatusCode, String)> {
+    if !headers.contains_key(PRINCIPAL_HEADER) {
+        return Ok(None);
+    }
+    let mut principal = String::new();
+    let mut expires = None;
+    let mut signed = vec![];
+    let mut attributes: HashMap<String, Vec<String>> = HashMap::new();
+    for key in headers.keys() {
+        let name = key.as_str();
+        if name == SIGNATURE_HEADER
+            || (name != PRINCIPAL_HEADER && !name.starts_with(ATTRIBUTE_PREFIX))
+        {
+            continue;
+        }
+        let mut values = headers.get_all(key).iter();
+        let (Some(value), None) = (values.next(), values.next()) else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Repeated principal header {}", name),
+            ));
+        };
+        let value = value.to_str().map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid principal header {}", name),
+            )
+        })?;
+        signed.push((name, value));
+        match name.strip_prefix(ATTRIBUTE_PREFIX) {
+            _ if name == EXPIRES_HEADER => expires = value.parse::<u64>().ok(),
+            Some(attribute) => {
+                attributes.insert(
+                    attribute.to_string(),
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|v| !v.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                );
+            }
+            None => principal = value.to_string(),
+        }
+    }
+    verify_signature(headers, secret, signed)?;
+    match expires {
+        Some(expires)
+            if expires > now && expires - now <= MAX_SIGNATURE_LIFETIME => {}
+        _ => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "Expired principal signature".to_string(),
+            ))
+        }
+    }
+    Ok(Some(Principal {
+        name: principal,
+        attributes,
+    }))
+}
+
+/// Checks the signature header against the signed principal headers, which
+/// no secret can be checked against when none is configured.
+fn verify_signature(
+    headers: &HeaderMap,
+    secret: Option<&str>,
+    mut signed: Vec<(&str, &str)>,
+) -> Result<(), (StatusCode, String)> {
+    let unauthorized =
+        |message: &str| Err((StatusCode::UNAUTHORIZED, message.to_string()));
+    let Some(secret) = secret else {
+        return unauthorized("Principal headers are not accepted");
+    };
+    let Some(signature) = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|signature| signature.to_str().ok())
+        .and_then(|signature| hex::decode(signature).ok())
+    else {
+        return unauthorized("Missing principal signature");
+    };
+    signed.sort();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    for (name, value) in signed {
+        mac.update(format!("{}:{}\n", name, value).as_bytes());
+    }
+    match mac.verify_slice(&signature) {
+        Ok(()) => Ok(()),
+        Err(_) => unauthorized("Invalid principal signature"),
+    }
+}
+
+fn planner_error(error: Error) -> (StatusCode, String) {
+    let status = match &error {
+        Error::Datasource(RegistryError::UnknownDatasource(_)) => {
+            StatusCode::NOT_FOUND
+        }
+        Error::Unauthorized(_) => StatusCode::FORBIDDEN,
+        Error::InvalidDatasource(_) | Error::InvalidSchema(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, format!("{:?}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+    const NOW: u64 = 1600000000;
+
+    fn signed_headers(headers: &[(&'static str, &str)]) -> HeaderMap {
+        let mut sorted = headers.to_vec();
+        sorted.sort();
+        let lines: Vec<String> = sorted
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(lines.concat().as_bytes());
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, value.parse().expect("Header should be valid"));
+        }
+        map.insert(
+            SIGNATURE_HEADER,
+            hex::encode(mac.finalize().into_bytes())
+                .parse()
+                .expect("Header should be valid"),
+        );
+        map
+    }
+
+    #[test]
+    fn test_principal_from_signed_headers() {
+        let headers = signed_headers(&[
+            ("x-principal", "alice"),
+            ("x-principal-expires", "1600000060"),
+            ("x-principal-tenant", "1, 2"),
+        ]);
+
+        let caller = principal(&headers, Some(SECRET), NOW)
+            .expect("Signature should verify")
+            .expect("Principal should be named");
+        assert_eq!(caller.name, "alice");
+        assert_eq!(
+            caller.attributes,
+            HashMap::from([(
+                "tenant".to_string(),
+                vec!["1".to_string(), "2".to_string()]
+            )])
+        );
+        assert!(matches!(
+            principal(&HeaderMap::new(), Some(SECRET), NOW),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn test_principal_rejects_repeated_attribute() {
+        let headers = signed_headers(&[
+            ("x-principal", "alice"),
+            ("x-principal-expires", "1600000060"),
+            ("x-principal-tenant", "1"),
+            ("x-principal-tenant", "2"),
+        ]);
+
+        assert!(matches!(
+            principal(&headers, Some(SECRET), NOW),
+            Err((StatusCode::BAD_REQUEST, _))
+        ));
+    }
+
+    #[test]
+    fn test_principal_rejects_unsigned_headers() {
+        let headers = signed_headers(&[
+            ("x-principal", "alice"),
+            ("x-principal-expires", "1600000060"),
+            ("x-principal-tenant", "1"),
+        ]);
+        assert!(matches!(
+            principal(&headers, None, NOW),
+            Err((StatusCode::UNAUTHORIZED, _))
+        ));
+        assert!(matches!(
+            principal(&headers, Some("other"), NOW),
+            Err((StatusCode::UNAUTHORIZED, _))
+        ));
+
+        let mut widened = headers.clone();
+        widened.insert(
+            "x-principal-tenant",
+            "1,2".parse().expect("Header should be valid"),
+        );
+        assert!(matches!(
+            principal(&widened, Some(SECRET), NOW),
+            Err((StatusCode::UNAUTHORIZED, _))
+        ));
+
+        let mut unsigned = headers;
+        unsigned.remove(SIGNATURE_HEADER);
+        assert!(matches!(
+            principal(&unsigned, Some(SECRET), NOW),
+            Err((StatusCode::UNAUTHORIZED, _))
+        ));
+    }
+
+    #[test]
+    fn test_principal_rejects_expired_signature() {
+        for expires in [
+            None,
+            Some("1599999999"),
+            Some("1600000000"),
+            Some("1600003600"),
+        ] {
+            let mut headers = vec![("x-principal", "alice")];
+            if let Some(expires) = expires {
+                headers.push(("x-principal-expires", expires));
+            }
+            let headers = signed_headers(&headers);
+            assert!(matches!(
+                principal(&headers, Some(SECRET), NOW),
+                Err((StatusCode::UNAUTHORIZED, _))
+            ));
+        }
+    }
+}
@@ -1,8 +1,17 @@
 use crate::api::repository::Repository;
+use crate::domain::models::Schema;
+use crate::domain::registry::DatasourceRegistry;
 
 pub mod handlers;
 pub mod repository;
 
 pub struct Env {
     pub repository: Repository,
+    /// Datasources loaded and validated once at startup.
+    pub registry: DatasourceRegistry,
+    /// Schema loaded once at startup, shared by every request.
+    pub schema: Schema,
+    /// Key the gateway signs the principal headers with, the headers being
+    /// refused when unset.
+    pub principal_secret: Option<String>,
 }
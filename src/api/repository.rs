@@ -1,19 +1,50 @@
-use crate::domain::models::Datasource;
-use crate::domain::models::Report;
-use sqlx::{Executor, PgPool};
+use crate::domain::models::{Datasource, Schema};
+use crate::domain::registry::{DatasourceRegistry, RegistryError};
+use sqlx::PgPool;
 use std::fs;
+use std::path::PathBuf;
 
+/// Directory every `.yaml` datasource definition is loaded from.
+const DATASOURCE_DIR: &str = "../test/datasources";
+/// Schema of the warehouse tables the datasources are planned over.
+const SCHEMA_PATH: &str = "../test/schema.yaml";
+
+#[derive(Debug)]
 pub enum PgError {
     Sqlx(sqlx::Error),
     Io(std::io::Error),
+    Yaml(serde_yml::Error),
+    Registry(Vec<RegistryError>),
+    /// Schema that no datasource can be planned over.
+    InvalidSchema(String),
 }
 pub struct Repository {
     pool: PgPool,
+    datasource_dir: PathBuf,
+    schema_path: PathBuf,
 }
 
 impl Repository {
     pub fn new(pool: PgPool) -> Repository {
-        Repository { pool }
+        Repository {
+            pool,
+            datasource_dir: DATASOURCE_DIR.into(),
+            schema_path: SCHEMA_PATH.into(),
+        }
+    }
+
+    pub fn with_datasource_dir(self, datasource_dir: PathBuf) -> Self {
+        Repository {
+            datasource_dir,
+            ..self
+        }
+    }
+
+    pub fn with_schema_path(self, schema_path: PathBuf) -> Self {
+        Repository {
+            schema_path,
+            ..self
+        }
     }
 
     pub async fn load_data(&self) -> Result<(i64,), sqlx::Error> {
@@ -23,10 +54,46 @@ impl Repository {
             .await
     }
 
-    pub fn load_datasources(&self) -> Vec<Datasource> {
-        let datasources =
-            fs::read_to_string("../test/datasource.yaml").unwrap();
-        serde_yml::from_str(datasources.as_str()).unwrap()
+    /// Loads and validates every datasource definition of the datasource
+    /// directory, in file name order.
+    pub fn load_datasources(&self) -> Result<DatasourceRegistry, PgError> {
+        let mut paths = fs::read_dir(&self.datasource_dir)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|e| e.path()))
+                    .collect::<Result<Vec<PathBuf>, std::io::Error>>()
+            })
+            .map_err(PgError::Io)?;
+        paths.retain(|path| {
+            matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("yaml" | "yml")
+            )
+        });
+        paths.sort();
+        let mut datasources = vec![];
+        for path in paths {
+            let datasource = fs::read_to_string(path).map_err(PgError::Io)?;
+            let datasource: Datasource =
+                serde_yml::from_str(datasource.as_str())
+                    .map_err(PgError::Yaml)?;
+            datasources.push(datasource);
+        }
+        DatasourceRegistry::new(datasources).map_err(PgError::Registry)
+    }
+
+    /// Loads the schema and checks it has a fact table to plan over.
+    pub fn load_schema(&self) -> Result<Schema, PgError> {
+        let schema =
+            fs::read_to_string(&self.schema_path).map_err(PgError::Io)?;
+        let schema: Schema =
+            serde_yml::from_str(schema.as_str()).map_err(PgError::Yaml)?;
+        match schema.fact_table() {
+            Some(_) => Ok(schema),
+            None => Err(PgError::InvalidSchema(
+                "Schema has no fact table".to_string(),
+            )),
+        }
     }
     // pub async fn create_report(&self, body: Report) -> Result<Report, PgError> {
     //     sqlx::query("insert into report body values ($1)")
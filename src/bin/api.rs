@@ -1,4 +1,4 @@
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::Router;
 use reporting::api::repository::Repository;
 use reporting::api::{handlers, Env};
@@ -18,8 +18,25 @@ async fn main() {
         .await
         .expect("Cannot connect to postgres");
 
-    let repository = Repository::new(pool);
-    let env = Arc::new(Env { repository });
+    let mut repository = Repository::new(pool);
+    if let Some(schema) = config.paths.schema {
+        repository = repository.with_schema_path(schema);
+    }
+    if let Some(datasources) = config.paths.datasources {
+        repository = repository.with_datasource_dir(datasources);
+    }
+    let registry = repository
+        .load_datasources()
+        .expect("Invalid datasource configuration");
+    let schema = repository
+        .load_schema()
+        .expect("Invalid schema configuration");
+    let env = Arc::new(Env {
+        repository,
+        registry,
+        schema,
+        principal_secret: config.auth.principal_secret,
+    });
 
     // build our application with a route
     let app = Router::new()
@@ -27,6 +44,7 @@ async fn main() {
         .route("/id/:id", get(handlers::report))
         .route("/datasources", get(handlers::get_datasources))
         .route("/query", get(handlers::query))
+        .route("/explain", post(handlers::explain))
         .with_state(env);
 
     // run our app with hyper, listening globally on port 3000
@@ -7,7 +7,7 @@ async fn main() {
     tracing_subscriber::fmt::init();
 
     let config = Settings::new().expect("settings parsing failed");
-    let pool = PgPoolOptions::new()
+    let _pool = PgPoolOptions::new()
         .max_connections(5)
         .connect(config.database.url.as_str())
         .await
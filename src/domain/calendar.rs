@@ -0,0 +1,223 @@
+use crate::domain::timezone::utc_offset;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Calendar date in the proleptic Gregorian calendar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    year: i32,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Date> {
+        let valid = (1..=12).contains(&month)
+            && (1..=days_in_month(year, month)).contains(&day);
+        valid.then_some(Date { year, month, day })
+    }
+
+    /// Parses a `YYYY-MM-DD` date.
+    pub fn parse(value: &str) -> Option<Date> {
+        let parts: Vec<&str> = value.split('-').collect();
+        let [year, month, day] = parts[..] else {
+            return None;
+        };
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return None;
+        }
+        if !(year.bytes().chain(month.bytes()).chain(day.bytes()))
+            .all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        Date::new(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)
+    }
+
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    pub fn month(&self) -> u32 {
+        self.month
+    }
+
+    pub fn day(&self) -> u32 {
+        self.day
+    }
+
+    /// Day of the week, 0 for Monday.
+    pub fn weekday(&self) -> u32 {
+        // 1970-01-01 was a Thursday.
+        (self.days_since_epoch() + 3).rem_euclid(7) as u32
+    }
+
+    /// Number of days from this date to `other`, negative if `other` is
+    /// earlier.
+    pub fn days_until(&self, other: Date) -> i64 {
+        other.days_since_epoch() - self.days_since_epoch()
+    }
+
+    pub fn add_days(&self, days: i64) -> Date {
+        Date::from_days_since_epoch(self.days_since_epoch() + days)
+    }
+
+    /// First day of the month `months` after this date's month.
+    pub fn add_months(&self, months: i32) -> Date {
+        let index = self.year * 12 + self.month as i32 - 1 + months;
+        Date {
+            year: index.div_euclid(12),
+            month: index.rem_euclid(12) as u32 + 1,
+            day: 1,
+        }
+    }
+
+    pub fn start_of_week(&self) -> Date {
+        self.add_days(-(self.weekday() as i64))
+    }
+
+    pub fn start_of_month(&self) -> Date {
+        self.add_months(0)
+    }
+
+    pub fn start_of_quarter(&self) -> Date {
+        self.add_months(-((self.month as i32 - 1) % 3))
+    }
+
+    pub fn start_of_year(&self) -> Date {
+        Date {
+            year: self.year,
+            month: 1,
+            day: 1,
+        }
+    }
+
+    // Howard Hinnant's days_from_civil and civil_from_days.
+    pub(crate) fn days_since_epoch(&self) -> i64 {
+        let month = self.month as i64;
+        let year = self.year as i64 - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year =
+            (153 * ((month + 9) % 12) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4
+            - year_of_era / 100
+            + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    pub(crate) fn from_days_since_epoch(days: i64) -> Date {
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+            - day_of_era / 146096)
+            / 365;
+        let day_of_year = day_of_era
+            - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = ((shifted_month + 2) % 12 + 1) as u32;
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+        Date {
+            year: year as i32,
+            month,
+            day,
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Source of the current date, so that relative date ranges can be resolved
+/// reproducibly.
+pub trait Clock {
+    /// Current date in the timezone, UTC when unset, or `None` when the
+    /// timezone is unknown.
+    fn today(&self, timezone: Option<&str>) -> Option<Date>;
+}
+
+/// Current date from the system time, in the system zone database.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn today(&self, timezone: Option<&str>) -> Option<Date> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let offset = match timezone {
+            Some(timezone) => utc_offset(timezone, seconds)?,
+            None => 0,
+        };
+        Some(Date::from_days_since_epoch(
+            (seconds + offset).div_euclid(86400),
+        ))
+    }
+}
+
+/// Clock stopped at a given date, whatever the timezone.
+pub struct FixedClock(pub Date);
+
+impl Clock for FixedClock {
+    fn today(&self, _timezone: Option<&str>) -> Option<Date> {
+        Some(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> Date {
+        Date::parse(value).expect("Date should be valid")
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(Date::parse("2020-02-29"), Date::new(2020, 2, 29));
+        assert_eq!(Date::parse("2021-02-29"), None);
+        assert_eq!(Date::parse("2020-1-01"), None);
+        assert_eq!(Date::parse("2020-+1-01"), None);
+        assert_eq!(date("2020-03-01").to_string(), "2020-03-01");
+    }
+
+    #[test]
+    fn test_date_arithmetic() {
+        assert_eq!(date("2020-03-01").add_days(-1), date("2020-02-29"));
+        assert_eq!(date("2020-12-31").add_days(1), date("2021-01-01"));
+        assert_eq!(date("1969-12-31").add_days(1), date("1970-01-01"));
+        assert_eq!(date("2020-01-15").add_months(-1), date("2019-12-01"));
+        assert_eq!(date("2020-11-15").add_months(3), date("2021-02-01"));
+        assert_eq!(date("2020-01-06").weekday(), 0);
+        assert_eq!(date("2020-01-01").days_until(date("2021-01-01")), 366);
+        assert_eq!(date("2020-01-01").start_of_week(), date("2019-12-30"));
+        assert_eq!(date("2020-08-20").start_of_quarter(), date("2020-07-01"));
+        assert_eq!(date("2020-08-20").start_of_year(), date("2020-01-01"));
+    }
+
+    #[test]
+    fn test_system_clock_timezones() {
+        let ahead = SystemClock.today(Some("+14:00")).expect("Offset is valid");
+        let behind =
+            SystemClock.today(Some("-12:00")).expect("Offset is valid");
+        // The offsets are 26 hours apart.
+        assert!((1..=2).contains(&behind.days_until(ahead)));
+        assert_eq!(SystemClock.today(Some("Mars/Olympus_Mons")), None);
+        assert_eq!(FixedClock(ahead).today(Some("-12:00")), Some(ahead));
+    }
+}
@@ -0,0 +1,707 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// Functions that aggregate rows, an expression calling any of them is an
+/// aggregate.
+const AGGREGATE_FUNCTIONS: [&str; 7] = [
+    "sum",
+    "count",
+    "min",
+    "max",
+    "avg",
+    "approx_distinct",
+    "count_distinct",
+];
+
+/// Whether a function aggregates rows, whatever the case it is written in.
+fn is_aggregate_function(name: &str) -> bool {
+    AGGREGATE_FUNCTIONS.contains(&name.to_lowercase().as_str())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    /// Unqualified name, a column id when used in a formula.
+    Identifier(Arc<str>),
+    Column {
+        table: Arc<str>,
+        column: Arc<str>,
+    },
+    Function {
+        name: Arc<str>,
+        args: Vec<Expression>,
+    },
+    Binary {
+        left: Box<Expression>,
+        operator: BinaryOperator,
+        right: Box<Expression>,
+    },
+    Negate(Box<Expression>),
+    Number(Arc<str>),
+    String(Arc<str>),
+    /// `*` argument, as in `count(*)`.
+    Wildcard,
+    /// `CASE operand WHEN value THEN result ... ELSE otherwise END`, built by
+    /// the planner.
+    Case {
+        operand: Box<Expression>,
+        branches: Vec<(Expression, Expression)>,
+        otherwise: Option<Box<Expression>>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl BinaryOperator {
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Add | BinaryOperator::Subtract => 1,
+            BinaryOperator::Multiply | BinaryOperator::Divide => 2,
+        }
+    }
+
+    pub(crate) fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnexpectedCharacter(usize, char),
+    UnterminatedString(usize),
+    UnexpectedToken(usize, String),
+    UnexpectedEnd,
+}
+
+impl Expression {
+    pub fn parse(input: &str) -> Result<Expression, ParseError> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+        };
+        let expression = parser.expression()?;
+        match parser.tokens.get(parser.position) {
+            Some((offset, token)) => {
+                Err(ParseError::UnexpectedToken(*offset, token.to_string()))
+            }
+            None => Ok(expression),
+        }
+    }
+
+    /// Unqualified names the expression refers to, in order of appearance.
+    pub fn identifiers(&self) -> Vec<Arc<str>> {
+        let mut identifiers = vec![];
+        self.walk(&mut |e| {
+            if let Expression::Identifier(name) = e {
+                if !identifiers.contains(name) {
+                    identifiers.push(name.clone());
+                }
+            }
+        });
+        identifiers
+    }
+
+    /// Tables of the qualified columns the expression refers to.
+    pub fn tables(&self) -> Vec<Arc<str>> {
+        let mut tables = vec![];
+        self.walk(&mut |e| {
+            if let Expression::Column { table, .. } = e {
+                if !tables.contains(table) {
+                    tables.push(table.clone());
+                }
+            }
+        });
+        tables
+    }
+
+    pub fn is_aggregate(&self) -> bool {
+        let mut aggregate = false;
+        self.walk(&mut |e| {
+            if let Expression::Function { name, .. } = e {
+                aggregate |= is_aggregate_function(name);
+            }
+        });
+        aggregate
+    }
+
+    /// Replaces every unqualified name with the expression returned by
+    /// `replace`.
+    pub fn replace_identifiers(
+        &self,
+        replace: &dyn Fn(&str) -> Expression,
+    ) -> Expression {
+        self.transform(&|e| match e {
+            Expression::Identifier(name) => Some(replace(name)),
+            _ => None,
+        })
+    }
+
+    /// Restricts the arguments of every aggregate function call to the rows
+    /// where `operand` equals `value`, so that `sum(x)` becomes
+    /// `sum(CASE operand WHEN value THEN x END)`.
+    pub fn filter_aggregates(
+        &self,
+        operand: &Expression,
+        value: &Expression,
+    ) -> Expression {
+        self.transform(&|e| match e {
+            Expression::Function { name, args }
+                if is_aggregate_function(name) =>
+            {
+                Some(Expression::Function {
+                    name: name.clone(),
+                    args: args
+                        .iter()
+                        .map(|arg| Expression::Case {
+                            operand: Box::new(operand.clone()),
+                            branches: vec![(
+                                value.clone(),
+                                match arg {
+                                    // count(*) counts the matching rows.
+                                    Expression::Wildcard => {
+                                        Expression::Number("1".into())
+                                    }
+                                    arg => arg.clone(),
+                                },
+                            )],
+                            otherwise: None,
+                        })
+                        .collect(),
+                })
+            }
+            _ => None,
+        })
+    }
+
+    /// Rebuilds the expression top down, replacing every subexpression for
+    /// which `replace` returns an expression.
+    pub fn transform(
+        &self,
+        replace: &dyn Fn(&Expression) -> Option<Expression>,
+    ) -> Expression {
+        if let Some(replaced) = replace(self) {
+            return replaced;
+        }
+        match self {
+            Expression::Function { name, args } => Expression::Function {
+                name: name.clone(),
+                args: args.iter().map(|a| a.transform(replace)).collect(),
+            },
+            Expression::Binary {
+                left,
+                operator,
+                right,
+            } => Expression::Binary {
+                left: Box::new(left.transform(replace)),
+                operator: *operator,
+                right: Box::new(right.transform(replace)),
+            },
+            Expression::Negate(inner) => {
+                Expression::Negate(Box::new(inner.transform(replace)))
+            }
+            Expression::Case {
+                operand,
+                branches,
+                otherwise,
+            } => Expression::Case {
+                operand: Box::new(operand.transform(replace)),
+                branches: branches
+                    .iter()
+                    .map(|(value, result)| {
+                        (value.transform(replace), result.transform(replace))
+                    })
+                    .collect(),
+                otherwise: otherwise
+                    .as_ref()
+                    .map(|o| Box::new(o.transform(replace))),
+            },
+            other => other.clone(),
+        }
+    }
+
+    fn walk(&self, visit: &mut dyn FnMut(&Expression)) {
+        visit(self);
+        match self {
+            Expression::Function { args, .. } => {
+                args.iter().for_each(|a| a.walk(visit))
+            }
+            Expression::Binary { left, right, .. } => {
+                left.walk(visit);
+                right.walk(visit);
+            }
+            Expression::Negate(inner) => inner.walk(visit),
+            Expression::Case {
+                operand,
+                branches,
+                otherwise,
+            } => {
+                operand.walk(visit);
+                for (value, result) in branches.iter() {
+                    value.walk(visit);
+                    result.walk(visit);
+                }
+                if let Some(otherwise) = otherwise {
+                    otherwise.walk(visit);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            Expression::Binary { operator, .. } => operator.precedence(),
+            _ => u8::MAX,
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Identifier(name) => write!(f, "{}", name),
+            Expression::Column { table, column } => {
+                write!(f, "{}.{}", table, column)
+            }
+            Expression::Function { name, args } => {
+                write!(f, "{}(", name)?;
+                for (index, arg) in args.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expression::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                // Left associative: the right operand needs parentheses on
+                // equal precedence too, e.g. `a - (b - c)`.
+                if left.precedence() < operator.precedence() {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", operator.symbol())?;
+                if right.precedence() <= operator.precedence() {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+            Expression::Negate(inner) => match inner.as_ref() {
+                Expression::Binary { .. } | Expression::Negate(_) => {
+                    write!(f, "-({})", inner)
+                }
+                _ => write!(f, "-{}", inner),
+            },
+            Expression::Number(value) => write!(f, "{}", value),
+            Expression::String(value) => {
+                write!(f, "'{}'", value.replace('\'', "''"))
+            }
+            Expression::Wildcard => write!(f, "*"),
+            Expression::Case {
+                operand,
+                branches,
+                otherwise,
+            } => {
+                write!(f, "CASE {}", operand)?;
+                for (value, result) in branches.iter() {
+                    write!(f, " WHEN {} THEN {}", value, result)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, " ELSE {}", otherwise)?;
+                }
+                write!(f, " END")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(String),
+    String(String),
+    Symbol(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Identifier(value) | Token::Number(value) => {
+                write!(f, "{}", value)
+            }
+            Token::String(value) => write!(f, "'{}'", value),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let mut tokens = vec![];
+    let mut index = 0;
+    while index < chars.len() {
+        let (offset, c) = chars[index];
+        if c.is_whitespace() {
+            index += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut value = String::new();
+            while index < chars.len()
+                && (chars[index].1.is_ascii_alphanumeric()
+                    || chars[index].1 == '_')
+            {
+                value.push(chars[index].1);
+                index += 1;
+            }
+            tokens.push((offset, Token::Identifier(value)));
+        } else if c.is_ascii_digit() {
+            let mut value = String::new();
+            while index < chars.len()
+                && (chars[index].1.is_ascii_digit() || chars[index].1 == '.')
+            {
+                value.push(chars[index].1);
+                index += 1;
+            }
+            if value.matches('.').count() > 1 || value.ends_with('.') {
+                return Err(ParseError::UnexpectedToken(offset, value));
+            }
+            tokens.push((offset, Token::Number(value)));
+        } else if c == '\'' {
+            let mut value = String::new();
+            index += 1;
+            loop {
+                match chars.get(index) {
+                    None => return Err(ParseError::UnterminatedString(offset)),
+                    Some((_, '\'')) => {
+                        // A doubled quote is an escaped quote.
+                        if let Some((_, '\'')) = chars.get(index + 1) {
+                            value.push('\'');
+                            index += 2;
+                        } else {
+                            index += 1;
+                            break;
+                        }
+                    }
+                    Some((_, c)) => {
+                        value.push(*c);
+                        index += 1;
+                    }
+                }
+            }
+            tokens.push((offset, Token::String(value)));
+        } else if "+-*/(),.".contains(c) {
+            tokens.push((offset, Token::Symbol(c)));
+            index += 1;
+        } else {
+            return Err(ParseError::UnexpectedCharacter(offset, c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the grammar
+///
+/// ```text
+/// expression := term (('+' | '-') term)*
+/// term       := unary (('*' | '/') unary)*
+/// unary      := '-' unary | primary
+/// primary    := number | string | name | name '.' name
+///             | name '(' [argument (',' argument)*] ')' | '(' expression ')'
+/// argument   := '*' | expression
+/// ```
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser {
+    fn expression(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.term()?;
+        while let Some(operator) = self.binary_operator(&['+', '-']) {
+            let right = self.term()?;
+            left = binary(left, operator, right);
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.unary()?;
+        while let Some(operator) = self.binary_operator(&['*', '/']) {
+            let right = self.unary()?;
+            left = binary(left, operator, right);
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, ParseError> {
+        if self.accept('-') {
+            return Ok(Expression::Negate(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, ParseError> {
+        let (offset, token) = self.next()?;
+        match token {
+            Token::Number(value) => Ok(Expression::Number(value.into())),
+            Token::String(value) => Ok(Expression::String(value.into())),
+            Token::Symbol('(') => {
+                let expression = self.expression()?;
+                self.expect(')')?;
+                Ok(expression)
+            }
+            Token::Identifier(name) => {
+                if self.accept('.') {
+                    match self.next()? {
+                        (_, Token::Identifier(column)) => {
+                            Ok(Expression::Column {
+                                table: name.into(),
+                                column: column.into(),
+                            })
+                        }
+                        (offset, token) => Err(ParseError::UnexpectedToken(
+                            offset,
+                            token.to_string(),
+                        )),
+                    }
+                } else if self.accept('(') {
+                    Ok(Expression::Function {
+                        name: name.into(),
+                        args: self.arguments()?,
+                    })
+                } else {
+                    Ok(Expression::Identifier(name.into()))
+                }
+            }
+            token => {
+                Err(ParseError::UnexpectedToken(offset, token.to_string()))
+            }
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Expression>, ParseError> {
+        let mut args = vec![];
+        if self.accept(')') {
+            return Ok(args);
+        }
+        loop {
+            if self.accept('*') {
+                args.push(Expression::Wildcard);
+            } else {
+                args.push(self.expression()?);
+            }
+            if self.accept(')') {
+                return Ok(args);
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn binary_operator(&mut self, symbols: &[char]) -> Option<BinaryOperator> {
+        let operator = match self.tokens.get(self.position) {
+            Some((_, Token::Symbol(symbol))) if symbols.contains(symbol) => {
+                match symbol {
+                    '+' => BinaryOperator::Add,
+                    '-' => BinaryOperator::Subtract,
+                    '*' => BinaryOperator::Multiply,
+                    _ => BinaryOperator::Divide,
+                }
+            }
+            _ => return None,
+        };
+        self.position += 1;
+        Some(operator)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), ParseError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(ParseError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn accept(&mut self, symbol: char) -> bool {
+        match self.tokens.get(self.position) {
+            Some((_, Token::Symbol(s))) if *s == symbol => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), ParseError> {
+        match self.next()? {
+            (_, Token::Symbol(s)) if s == symbol => Ok(()),
+            (offset, token) => {
+                Err(ParseError::UnexpectedToken(offset, token.to_string()))
+            }
+        }
+    }
+}
+
+pub fn binary(
+    left: Expression,
+    operator: BinaryOperator,
+    right: Expression,
+) -> Expression {
+    Expression::Binary {
+        left: Box::new(left),
+        operator,
+        right: Box::new(right),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_expression() {
+        let expression =
+            Expression::parse("sum(fact_table.spend) / count(*) * 1000")
+                .expect("Could not parse expression");
+        assert_eq!(
+            expression,
+            binary(
+                binary(
+                    Expression::Function {
+                        name: Arc::from("sum"),
+                        args: vec![Expression::Column {
+                            table: Arc::from("fact_table"),
+                            column: Arc::from("spend"),
+                        }],
+                    },
+                    BinaryOperator::Divide,
+                    Expression::Function {
+                        name: Arc::from("count"),
+                        args: vec![Expression::Wildcard],
+                    },
+                ),
+                BinaryOperator::Multiply,
+                Expression::Number(Arc::from("1000")),
+            )
+        );
+        assert!(expression.is_aggregate());
+        assert_eq!(expression.tables(), vec![Arc::from("fact_table")]);
+        assert!(expression.identifiers().is_empty());
+    }
+
+    #[test]
+    fn test_render_expression() {
+        for (input, rendered) in [
+            ("a-(b-c)", "a - (b - c)"),
+            ("(a-b)-c", "a - b - c"),
+            ("(a+b)*c/2.5", "(a + b) * c / 2.5"),
+            ("-(a+b)", "-(a + b)"),
+            (
+                "from_unixtime(fact_table.ts,'YYYY-mm-dd')",
+                "from_unixtime(fact_table.ts, 'YYYY-mm-dd')",
+            ),
+            ("concat('it''s', name)", "concat('it''s', name)"),
+        ] {
+            let expression =
+                Expression::parse(input).expect("Could not parse expression");
+            assert_eq!(expression.to_string(), rendered);
+        }
+    }
+
+    #[test]
+    fn test_replace_identifiers() {
+        let expression =
+            Expression::parse("sum_spend / sum_impressions * 1000")
+                .expect("Could not parse expression");
+        assert_eq!(
+            expression.identifiers(),
+            vec![Arc::from("sum_spend"), Arc::from("sum_impressions")]
+        );
+        assert!(!expression.is_aggregate());
+        let replaced =
+            expression.replace_identifiers(&|name| Expression::Column {
+                table: Arc::from("facts"),
+                column: Arc::from(name),
+            });
+        assert_eq!(
+            replaced.to_string(),
+            "facts.sum_spend / facts.sum_impressions * 1000"
+        );
+    }
+
+    #[test]
+    fn test_filter_aggregates() {
+        let expression =
+            Expression::parse("coalesce(sum(fact_table.spend), 0) / count(*)")
+                .expect("Expression should parse");
+        let filtered = expression.filter_aggregates(
+            &Expression::Identifier("campaign_id".into()),
+            &Expression::Number("7".into()),
+        );
+        assert_eq!(
+            filtered.to_string(),
+            "coalesce(sum(CASE campaign_id WHEN 7 THEN fact_table.spend END), 0) / count(CASE campaign_id WHEN 7 THEN 1 END)"
+        );
+
+        let expression = Expression::parse("SUM(impressions)")
+            .expect("Expression should parse");
+        let filtered = expression.filter_aggregates(
+            &Expression::Identifier("campaign_id".into()),
+            &Expression::Number("7".into()),
+        );
+        assert_eq!(
+            filtered.to_string(),
+            "SUM(CASE campaign_id WHEN 7 THEN impressions END)"
+        );
+    }
+
+    #[test]
+    fn test_render_case() {
+        let expression = Expression::Case {
+            operand: Box::new(Expression::Identifier(Arc::from("level"))),
+            branches: vec![(
+                Expression::Number(Arc::from("0")),
+                Expression::String(Arc::from("detail")),
+            )],
+            otherwise: Some(Box::new(Expression::String(Arc::from("total")))),
+        };
+        assert_eq!(
+            expression.to_string(),
+            "CASE level WHEN 0 THEN 'detail' ELSE 'total' END"
+        );
+        assert_eq!(expression.identifiers(), vec![Arc::from("level")]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Expression::parse("sum(a"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(
+            Expression::parse("a + 'b"),
+            Err(ParseError::UnterminatedString(4))
+        );
+        assert_eq!(
+            Expression::parse("a ; b"),
+            Err(ParseError::UnexpectedCharacter(2, ';'))
+        );
+        assert_eq!(
+            Expression::parse("a b"),
+            Err(ParseError::UnexpectedToken(2, "b".to_string()))
+        );
+    }
+}
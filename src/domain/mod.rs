@@ -1,14 +1,18 @@
+pub mod calendar;
+pub mod expression;
 pub mod models;
+pub mod registry;
 pub mod service;
+pub mod timezone;
 
 #[cfg(test)]
 pub mod tests {
-    use crate::domain::models::ReportRequest;
     use crate::domain::tests::TestError::{LoadFile, ParseJson, ParseYaml};
     use serde::de::DeserializeOwned;
     use std::fs;
 
     #[derive(Debug)]
+    #[allow(dead_code)]
     pub(crate) enum TestError {
         LoadFile(String, String),
         ParseJson(String, String),
@@ -1,18 +1,262 @@
+use crate::domain::calendar::{Clock, Date};
+use crate::domain::expression::{Expression, ParseError};
 use serde::{Deserialize, Serialize};
-use std::rc::Rc;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Datasource {
-    pub name: Rc<str>,
+    pub name: Arc<str>,
     pub columns: Vec<Column>,
+    /// Timezone dates are bucketed and filtered in when the request does not
+    /// name one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<Arc<str>>,
+    /// Row restrictions applied to every request, whatever it filters on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<Policy>,
+}
+
+/// Security policy restricting a grouping column to the values the
+/// principal holds for an attribute, e.g. `advertiser_id` to the
+/// advertisers of an agency.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub column: Arc<str>,
+    pub attribute: Arc<str>,
+}
+
+/// Authenticated caller of a request, with the attribute values security
+/// policies restrict rows to.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Principal {
+    pub name: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, Vec<String>>,
+}
+
+impl Principal {
+    /// Values of an attribute, none when the principal lacks it.
+    pub fn values(&self, attribute: &str) -> &[String] {
+        self.attributes
+            .get(attribute)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    DuplicateColumn(String),
+    EmptyExpression(String),
+    InvalidExpression(String, ParseError),
+    /// Grouping or formula expression calling an aggregate function.
+    UnexpectedAggregate(String),
+    /// Aggregate expression not calling any aggregate function.
+    MissingAggregate(String),
+    MissingReference(String, String),
+    InvalidTimezone(String),
+    /// Running sum or share of total not naming an aggregate or formula
+    /// column.
+    InvalidMeasure(String),
+    /// Formula referring to a running sum or share of total.
+    WindowReference(String, String),
+    UnknownDataType(String, String),
+    /// Security policy not restricting a grouping column.
+    InvalidPolicy(String),
+}
+
+impl Datasource {
+    /// Output columns the pivot of a request generates for the aggregates
+    /// it asks for, in request order.
+    pub fn pivot_columns(&self, request: &ReportRequest) -> Vec<PivotColumn> {
+        let Some(pivot) = &request.pivot else {
+            return vec![];
+        };
+        let aggregates: Vec<&str> = request
+            .columns
+            .iter()
+            .map(String::as_str)
+            .filter(|id| {
+                self.columns.iter().any(|c| {
+                    c.column_id.as_ref() == *id
+                        && c.column_type == ColumnType::Aggregate
+                })
+            })
+            .collect();
+        pivot.columns(&aggregates)
+    }
+
+    /// Timezone of a report: the one of the request, else the datasource's.
+    pub fn report_timezone<'a>(
+        &'a self,
+        request: &'a ReportRequest,
+    ) -> Option<&'a str> {
+        request.timezone.as_deref().or(self.timezone.as_deref())
+    }
+
+    /// Filters of the request with relative date ranges resolved as of the
+    /// current date of the clock in the report timezone, `None` when the
+    /// clock does not know the timezone.
+    pub fn resolve_filters(
+        &self,
+        request: &ReportRequest,
+        clock: &dyn Clock,
+    ) -> Option<Filter> {
+        if !request.filters.has_relative() {
+            return Some(request.filters.clone());
+        }
+        let today = clock.today(self.report_timezone(request))?;
+        Some(request.filters.resolve_relative(today))
+    }
+
+    /// Checks the column definitions, reporting every problem found.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+        for (index, column) in self.columns.iter().enumerate() {
+            let id = column.column_id.to_string();
+            let duplicate = self.columns[..index]
+                .iter()
+                .any(|c| c.column_id == column.column_id);
+            if duplicate
+                && !errors
+                    .contains(&ValidationError::DuplicateColumn(id.clone()))
+            {
+                errors.push(ValidationError::DuplicateColumn(id.clone()));
+            }
+            if let DataType::Unknown(name) = &column.data_type {
+                errors.push(ValidationError::UnknownDataType(
+                    id.clone(),
+                    name.clone(),
+                ));
+            }
+            if column.expression.trim().is_empty() {
+                errors.push(ValidationError::EmptyExpression(id.clone()));
+            } else {
+                match column.parse_expression() {
+                    Ok(expression) => {
+                        let aggregate = expression.is_aggregate();
+                        match column.column_type {
+                            ColumnType::Aggregate if !aggregate => errors.push(
+                                ValidationError::MissingAggregate(id.clone()),
+                            ),
+                            ColumnType::Grouping | ColumnType::Formula
+                                if aggregate =>
+                            {
+                                errors.push(
+                                    ValidationError::UnexpectedAggregate(
+                                        id.clone(),
+                                    ),
+                                )
+                            }
+                            _ => {}
+                        }
+                    }
+                    Err(e) => errors.push(ValidationError::InvalidExpression(
+                        id.clone(),
+                        e,
+                    )),
+                }
+            }
+            if column.column_type == ColumnType::Formula {
+                for reference in column.references() {
+                    match self.columns.iter().find(|c| c.column_id == reference)
+                    {
+                        None => errors.push(ValidationError::MissingReference(
+                            id.clone(),
+                            reference.to_string(),
+                        )),
+                        Some(c) if c.column_type.is_window() => {
+                            errors.push(ValidationError::WindowReference(
+                                id.clone(),
+                                reference.to_string(),
+                            ))
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+            if column.column_type.is_window() {
+                let measure = column.measure().and_then(|measure| {
+                    self.columns.iter().find(|c| c.column_id == measure)
+                });
+                if !measure.is_some_and(|m| {
+                    matches!(
+                        m.column_type,
+                        ColumnType::Aggregate | ColumnType::Formula
+                    )
+                }) {
+                    errors.push(ValidationError::InvalidMeasure(id.clone()));
+                }
+            }
+        }
+        if let Some(timezone) = &self.timezone {
+            if !is_timezone(timezone) {
+                errors.push(ValidationError::InvalidTimezone(
+                    timezone.to_string(),
+                ));
+            }
+        }
+        for policy in self.policies.iter() {
+            if !self.columns.iter().any(|c| {
+                c.column_id == policy.column
+                    && c.column_type == ColumnType::Grouping
+            }) {
+                errors.push(ValidationError::InvalidPolicy(
+                    policy.column.to_string(),
+                ));
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+/// Whether a name looks like an IANA zone (`Europe/Berlin`) or a UTC
+/// offset (`+02:00`). The warehouse resolves the zone itself.
+pub fn is_timezone(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"/_+-:".contains(&b))
 }
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Column {
-    pub name: Rc<str>,
-    pub column_id: Rc<str>,
-    pub expression: Rc<str>,
+    pub name: Arc<str>,
+    pub column_id: Arc<str>,
+    pub expression: Arc<str>,
     pub column_type: ColumnType,
-    pub data_type: Rc<str>,
+    pub data_type: DataType,
+    /// Grouping column ids an aggregate can be broken down by, any grouping
+    /// when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compatible_groupings: Option<Vec<Arc<str>>>,
+}
+
+impl Column {
+    pub fn parse_expression(&self) -> Result<Expression, ParseError> {
+        Expression::parse(&self.expression)
+    }
+
+    /// Column ids referenced by the expression, in order of appearance.
+    /// Function names, qualified table columns, numbers and string
+    /// literals are not references.
+    pub fn references(&self) -> Vec<Arc<str>> {
+        self.parse_expression()
+            .map(|e| e.identifiers())
+            .unwrap_or_default()
+    }
+
+    /// Column id a running sum or share of total is computed over, its
+    /// whole expression.
+    pub fn measure(&self) -> Option<Arc<str>> {
+        match self.parse_expression() {
+            Ok(Expression::Identifier(measure)) => Some(measure),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -21,6 +265,172 @@ pub enum ColumnType {
     Grouping,
     Aggregate,
     Formula,
+    /// Cumulative sum of a measure in the requested sort order.
+    RunningSum,
+    /// Percentage of a measure over all rows of the report.
+    ShareOfTotal,
+}
+
+impl ColumnType {
+    /// Computed with a window function over the rows of the report.
+    pub fn is_window(&self) -> bool {
+        matches!(self, ColumnType::RunningSum | ColumnType::ShareOfTotal)
+    }
+
+    /// Computed over the aggregated facts rather than the fact rows.
+    pub fn is_derived(&self) -> bool {
+        *self == ColumnType::Formula || self.is_window()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DataType {
+    I32,
+    I64,
+    Dec64,
+    Text,
+    Date,
+    /// Type name not known to the planner, reported by
+    /// [`Datasource::validate`] with every other problem.
+    #[serde(untagged)]
+    Unknown(String),
+}
+
+/// Filter or cursor value checked against the type of its column.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal {
+    Integer(i64),
+    /// Decimal digits with an optional sign and fraction, kept as text so
+    /// no precision is lost.
+    Decimal(Arc<str>),
+    Text(Arc<str>),
+    /// `YYYY-MM-DD` calendar date.
+    Date(Arc<str>),
+    /// `YYYY-MM-DD HH:MM:SS` time, in the timezone of the column it is
+    /// compared to.
+    DateTime(Arc<str>),
+    /// Midnight of a `YYYY-MM-DD` date in a timezone.
+    Timestamp {
+        date: Arc<str>,
+        timezone: Arc<str>,
+    },
+}
+
+impl DataType {
+    /// Parses a value sent as a string into a literal of this type.
+    pub fn parse_literal(&self, value: &str) -> Option<Literal> {
+        match self {
+            DataType::I32 => value
+                .parse::<i32>()
+                .ok()
+                .map(|v| Literal::Integer(v.into())),
+            DataType::I64 => value.parse::<i64>().ok().map(Literal::Integer),
+            DataType::Dec64 => {
+                is_decimal(value).then(|| Literal::Decimal(value.into()))
+            }
+            DataType::Text => Some(Literal::Text(value.into())),
+            DataType::Date => {
+                Date::parse(value).map(|_| Literal::Date(value.into()))
+            }
+            DataType::Unknown(_) => None,
+        }
+    }
+}
+
+fn is_decimal(value: &str) -> bool {
+    let unsigned = value.strip_prefix('-').unwrap_or(value);
+    let (integer, fraction) = match unsigned.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let digits =
+        |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    digits(integer) && fraction.is_none_or(digits)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Schema {
+    pub name: Arc<str>,
+    pub tables: Vec<Table>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Table {
+    pub name: Arc<str>,
+    #[serde(rename = "type")]
+    pub table_type: TableType,
+    /// Join column shared with the fact table (hierarchies) or with the
+    /// aggregated facts (dimensions).
+    #[serde(default)]
+    pub column: Option<Arc<str>>,
+    /// Unix time column of the fact table, bucketed by the requested
+    /// granularity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Arc<str>>,
+    /// What an aggregate table has pre-aggregated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<AggregateTable>,
+}
+
+/// Aggregates of a fact table pre-computed by some grouping columns, in
+/// columns named after the datasource column ids.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AggregateTable {
+    /// Fact table the aggregates were computed from.
+    pub fact: Arc<str>,
+    /// Grouping column ids kept by the table.
+    pub groupings: Vec<Arc<str>>,
+    /// Bucket of the `date` grouping, in UTC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub granularity: Option<Granularity>,
+    /// Aggregate column ids computed by the table.
+    pub measures: Vec<Arc<str>>,
+    /// Approximate number of rows, the smallest table answering a request
+    /// is used.
+    pub rows: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TableType {
+    Fact,
+    Hierarchy,
+    Dimension,
+    Aggregate,
+}
+
+impl Schema {
+    /// The first fact table, whose rows are counted when a request needs
+    /// no other fact.
+    pub fn fact_table(&self) -> Option<&Table> {
+        self.tables_of(TableType::Fact).next()
+    }
+
+    pub fn facts(&self) -> impl Iterator<Item = &Table> {
+        self.tables_of(TableType::Fact)
+    }
+
+    pub fn aggregates(
+        &self,
+    ) -> impl Iterator<Item = (&Table, &AggregateTable)> {
+        self.tables_of(TableType::Aggregate)
+            .filter_map(|t| t.aggregate.as_ref().map(|a| (t, a)))
+    }
+
+    pub fn hierarchies(&self) -> impl Iterator<Item = &Table> {
+        self.tables_of(TableType::Hierarchy)
+    }
+
+    pub fn dimensions(&self) -> impl Iterator<Item = &Table> {
+        self.tables_of(TableType::Dimension)
+    }
+
+    fn tables_of(&self, table_type: TableType) -> impl Iterator<Item = &Table> {
+        self.tables
+            .iter()
+            .filter(move |t| t.table_type == table_type)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
@@ -32,30 +442,405 @@ pub enum ReportStatus {
     Expired,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Filter {
-    And { value: Vec<Filter> },
-    Or { value: Vec<Filter> },
-    Eq { column: String, value: String },
-    Lt { column: String, value: String },
-    Lte { column: String, value: String },
-    Gt { column: String, value: String },
-    Gte { column: String, value: String },
+    And {
+        value: Vec<Filter>,
+    },
+    Or {
+        value: Vec<Filter>,
+    },
+    Eq {
+        column: String,
+        value: String,
+    },
+    Lt {
+        column: String,
+        value: String,
+    },
+    Lte {
+        column: String,
+        value: String,
+    },
+    Gt {
+        column: String,
+        value: String,
+    },
+    Gte {
+        column: String,
+        value: String,
+    },
+    /// Date range relative to the planning date, see
+    /// [`Filter::resolve_relative`].
+    Relative {
+        column: String,
+        range: RelativeRange,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelativeRange {
+    Today,
+    Yesterday,
+    #[serde(rename = "last_7_days")]
+    Last7Days,
+    #[serde(rename = "last_30_days")]
+    Last30Days,
+    WeekToDate,
+    PreviousWeek,
+    MonthToDate,
+    PreviousMonth,
+    QuarterToDate,
+    PreviousQuarter,
+    YearToDate,
+    PreviousYear,
+}
+
+impl RelativeRange {
+    /// First day and the day after the last day of the range. Ranges "to
+    /// date" include today, the "last" ranges end yesterday.
+    pub fn bounds(&self, today: Date) -> (Date, Date) {
+        let tomorrow = today.add_days(1);
+        match self {
+            RelativeRange::Today => (today, tomorrow),
+            RelativeRange::Yesterday => (today.add_days(-1), today),
+            RelativeRange::Last7Days => (today.add_days(-7), today),
+            RelativeRange::Last30Days => (today.add_days(-30), today),
+            RelativeRange::WeekToDate => (today.start_of_week(), tomorrow),
+            RelativeRange::PreviousWeek => {
+                let start = today.start_of_week();
+                (start.add_days(-7), start)
+            }
+            RelativeRange::MonthToDate => (today.start_of_month(), tomorrow),
+            RelativeRange::PreviousMonth => {
+                let start = today.start_of_month();
+                (start.add_months(-1), start)
+            }
+            RelativeRange::QuarterToDate => {
+                (today.start_of_quarter(), tomorrow)
+            }
+            RelativeRange::PreviousQuarter => {
+                let start = today.start_of_quarter();
+                (start.add_months(-3), start)
+            }
+            RelativeRange::YearToDate => (today.start_of_year(), tomorrow),
+            RelativeRange::PreviousYear => {
+                let start = today.start_of_year();
+                (start.add_months(-12), start)
+            }
+        }
+    }
+}
+
+impl Filter {
+    /// Column ids the filter refers to, including nested filters.
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Filter::And { value } | Filter::Or { value } => {
+                value.iter().flat_map(|f| f.columns()).collect()
+            }
+            Filter::Eq { column, .. }
+            | Filter::Lt { column, .. }
+            | Filter::Lte { column, .. }
+            | Filter::Gt { column, .. }
+            | Filter::Gte { column, .. }
+            | Filter::Relative { column, .. } => vec![column.as_str()],
+        }
+    }
+
+    /// Replaces relative ranges with `Gte` and `Lt` bounds as of `today`.
+    /// Bounds of a range directly in an `And` are spliced into it, so a
+    /// top level range satisfies the required date range.
+    pub fn resolve_relative(&self, today: Date) -> Filter {
+        match self {
+            Filter::And { value } => Filter::And {
+                value: value
+                    .iter()
+                    .flat_map(|f| match f.resolve_relative(today) {
+                        Filter::And { value } if f.is_relative() => value,
+                        resolved => vec![resolved],
+                    })
+                    .collect(),
+            },
+            Filter::Or { value } => Filter::Or {
+                value: value
+                    .iter()
+                    .map(|f| f.resolve_relative(today))
+                    .collect(),
+            },
+            Filter::Relative { column, range } => {
+                let (start, end) = range.bounds(today);
+                Filter::And {
+                    value: vec![
+                        Filter::Gte {
+                            column: column.clone(),
+                            value: start.to_string(),
+                        },
+                        Filter::Lt {
+                            column: column.clone(),
+                            value: end.to_string(),
+                        },
+                    ],
+                }
+            }
+            other => other.clone(),
+        }
+    }
+
+    fn is_relative(&self) -> bool {
+        matches!(self, Filter::Relative { .. })
+    }
+
+    /// Whether the filter has relative ranges, including nested filters.
+    fn has_relative(&self) -> bool {
+        match self {
+            Filter::And { value } | Filter::Or { value } => {
+                value.iter().any(Filter::has_relative)
+            }
+            other => other.is_relative(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(tag = "dir", rename_all = "snake_case")]
 pub enum Order {
-    Asc { column: String },
-    Desc { column: String },
+    Asc {
+        column: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nulls: Option<Nulls>,
+    },
+    Desc {
+        column: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nulls: Option<Nulls>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+impl Order {
+    pub fn column(&self) -> &str {
+        match self {
+            Order::Asc { column, .. } | Order::Desc { column, .. } => column,
+        }
+    }
+
+    pub fn nulls(&self) -> Option<Nulls> {
+        match self {
+            Order::Asc { nulls, .. } | Order::Desc { nulls, .. } => *nulls,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ReportRequest {
+    /// Name of the datasource to report on, the only registered one when
+    /// unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datasource: Option<String>,
     pub columns: Vec<String>,
     pub filters: Filter,
     pub sort: Vec<Order>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    /// Opaque keyset cursor, see [`Cursor`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// Bucket of the date column, the datasource expression when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub granularity: Option<Granularity>,
+    /// Timezone of the date column and date filters, overrides the
+    /// datasource timezone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Period to compare the requested date range with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compare: Option<Comparison>,
+    /// Subtotal and total rows to add to the requested groupings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totals: Option<Totals>,
+    /// Keeps only the first rows by the requested sort per group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_n: Option<TopN>,
+    /// Spreads the requested aggregates over one column per value of a
+    /// grouping column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pivot: Option<Pivot>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Pivot {
+    /// Requested grouping column whose values become columns.
+    pub column: String,
+    pub values: Vec<String>,
+}
+
+impl Pivot {
+    /// Output columns of the given aggregates, one per aggregate and value,
+    /// grouped by aggregate.
+    pub fn columns(&self, aggregates: &[&str]) -> Vec<PivotColumn> {
+        aggregates
+            .iter()
+            .flat_map(|aggregate| {
+                self.values.iter().map(move |value| PivotColumn {
+                    column_id: format!("{}_{}", aggregate, identifier(value)),
+                    aggregate: aggregate.to_string(),
+                    value: value.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Output column generated by a pivot: an aggregate restricted to one
+/// value of the pivot column.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PivotColumn {
+    pub column_id: String,
+    pub aggregate: String,
+    pub value: String,
+}
+
+/// Lowercase alphanumerics of a value, anything else replaced by `_`.
+fn identifier(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+/// The first `limit` rows, ordered by the requested sort, for every
+/// combination of the `per` grouping columns.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TopN {
+    pub limit: u64,
+    #[serde(default)]
+    pub per: Vec<String>,
+}
+
+/// Grouping levels to total at, besides the detail rows.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Totals {
+    /// Every prefix of the requested groupings, down to the grand total.
+    Rollup,
+    /// Every combination of the requested groupings.
+    Cube,
+    /// The given grouping column ids per level, `[]` is the grand total.
+    Levels(Vec<Vec<String>>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    /// The range of the same length right before the requested one.
+    PreviousPeriod,
+}
+
+impl Comparison {
+    /// Bounds of the compared period for the requested `[start, end)`.
+    pub fn period(&self, start: Date, end: Date) -> (Date, Date) {
+        match self {
+            Comparison::PreviousPeriod => {
+                (start.add_days(-start.days_until(end)), start)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Granularity {
+    /// Unit name understood by `date_trunc`.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+            Granularity::Month => "month",
+            Granularity::Quarter => "quarter",
+            Granularity::Year => "year",
+        }
+    }
+
+    /// Whether every bucket is made of whole buckets of `finer`, so that
+    /// data bucketed by `finer` can be bucketed again by this granularity.
+    pub fn contains(&self, finer: Granularity) -> bool {
+        match (self, finer) {
+            (Granularity::Week, _) => finer <= Granularity::Week,
+            (_, Granularity::Week) => false,
+            _ => finer <= *self,
+        }
+    }
+
+    /// Whether a `YYYY-MM-DD` date starts a bucket. Weeks start on Monday.
+    pub fn is_aligned(&self, date: &str) -> bool {
+        let Some(date) = Date::parse(date) else {
+            return false;
+        };
+        match self {
+            Granularity::Hour | Granularity::Day => true,
+            Granularity::Week => date == date.start_of_week(),
+            Granularity::Month => date == date.start_of_month(),
+            Granularity::Quarter => date == date.start_of_quarter(),
+            Granularity::Year => date == date.start_of_year(),
+        }
+    }
+
+    /// Start of the bucket after the one starting at `date`. Date filters
+    /// name days, so hours are bounded by the next day.
+    pub fn next_bucket(&self, date: Date) -> Date {
+        match self {
+            Granularity::Hour | Granularity::Day => date.add_days(1),
+            Granularity::Week => date.add_days(7),
+            Granularity::Month => date.add_months(1),
+            Granularity::Quarter => date.add_months(3),
+            Granularity::Year => date.add_months(12),
+        }
+    }
+}
+
+/// Position in a paginated report: the sort key values of the last row of
+/// the previous page, `None` for NULL. Sent to clients as an opaque string.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Cursor {
+    pub values: Vec<Option<String>>,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let json =
+            serde_json::to_string(&self.values).expect("strings serialize");
+        hex::encode(json)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        let bytes = hex::decode(cursor).ok()?;
+        let values = serde_json::from_slice(&bytes).ok()?;
+        Some(Cursor { values })
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -68,8 +853,15 @@ pub struct ReportMetadata {
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Report {
-    pub id: Rc<str>,
+    pub id: Arc<str>,
     pub request: ReportRequest,
+    /// Request filters with relative date ranges resolved when the report
+    /// was created, so that reruns cover the same dates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_filters: Option<Filter>,
+    /// Output columns generated by the pivot of the request.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pivot_columns: Vec<PivotColumn>,
     pub status: ReportStatus,
     pub metadata: Option<ReportMetadata>,
 }
@@ -101,12 +893,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_deserialize_sort() {
+        let sort: Vec<Order> = serde_json::from_str(
+            r#"[{"dir": "asc", "column": "date"},
+                {"dir": "desc", "column": "cpm", "nulls": "last"}]"#,
+        )
+        .expect("Could not parse sort json");
+        assert!(matches!(
+            &sort[0],
+            Order::Asc { column, nulls: None } if column == "date"
+        ));
+        assert!(matches!(
+            &sort[1],
+            Order::Desc { column, nulls: Some(Nulls::Last) } if column == "cpm"
+        ));
+    }
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let cursor = Cursor {
+            values: vec![
+                Some("2020-01-01".to_string()),
+                None,
+                Some("it's 7".to_string()),
+            ],
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(Cursor::decode("not a cursor"), None);
+    }
+
     #[test]
     fn test_deserialize_datasource() {
-        let datasource_file = "test/datasource.yaml";
+        let datasource_file = "test/datasources/default.yaml";
         let datasource: Datasource =
             load_yaml(datasource_file).expect("Could not parse request yaml");
-        assert_eq!(datasource.name, Rc::from("default"));
+        assert_eq!(datasource.name, Arc::from("default"));
         datasource
             .columns
             .iter()
@@ -121,7 +943,7 @@ mod tests {
                     )
                 }
                 "insertion_order_id" => {
-                    assert_eq!(c.data_type.to_string(), "i32".to_string())
+                    assert_eq!(c.data_type, DataType::I32)
                 }
                 "line_item_id" => {
                     assert_eq!(c.name.to_string(), "T_LINE_ITEM_ID".to_string())
@@ -132,10 +954,329 @@ mod tests {
                         "sum(impressions)".to_string()
                     )
                 }
-                "sum_spend" => {
-                    assert_eq!(c.data_type.to_string(), "dec64".to_string())
-                }
+                "sum_spend" => assert_eq!(c.data_type, DataType::Dec64),
+                "cpm" => assert_eq!(c.column_type, ColumnType::Formula),
                 _ => unreachable!(),
-            })
+            });
+        assert_eq!(datasource.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_datasource() {
+        let column = Column {
+            name: Arc::from("T_SPEND"),
+            column_id: Arc::from("sum_spend"),
+            expression: Arc::from("sum(spend)"),
+            column_type: ColumnType::Aggregate,
+            data_type: DataType::Dec64,
+            compatible_groupings: None,
+        };
+        let datasource = Datasource {
+            name: Arc::from("default"),
+            timezone: Some(Arc::from("Europe/Berlin'")),
+            policies: vec![Policy {
+                column: Arc::from("sum_spend"),
+                attribute: Arc::from("advertisers"),
+            }],
+            columns: vec![
+                column.clone(),
+                Column {
+                    expression: Arc::from(" "),
+                    ..column.clone()
+                },
+                Column {
+                    column_id: Arc::from("cpm"),
+                    expression: Arc::from("sum_spend / sum_impressions * 1000"),
+                    column_type: ColumnType::Formula,
+                    ..column.clone()
+                },
+                Column {
+                    column_id: Arc::from("impressions"),
+                    expression: Arc::from("impressions"),
+                    ..column.clone()
+                },
+                Column {
+                    column_id: Arc::from("campaign_id"),
+                    expression: Arc::from("max(campaign_id"),
+                    column_type: ColumnType::Grouping,
+                    ..column.clone()
+                },
+                Column {
+                    column_id: Arc::from("cumulative_cpm"),
+                    expression: Arc::from("cpm"),
+                    column_type: ColumnType::RunningSum,
+                    ..column.clone()
+                },
+                Column {
+                    column_id: Arc::from("cpm_of_cumulative"),
+                    expression: Arc::from("cumulative_cpm * 2"),
+                    column_type: ColumnType::Formula,
+                    ..column.clone()
+                },
+                Column {
+                    column_id: Arc::from("share_of_campaign"),
+                    expression: Arc::from("campaign_id"),
+                    column_type: ColumnType::ShareOfTotal,
+                    ..column
+                },
+            ],
+        };
+        assert_eq!(
+            datasource.validate(),
+            Err(vec![
+                ValidationError::DuplicateColumn("sum_spend".to_string()),
+                ValidationError::EmptyExpression("sum_spend".to_string()),
+                ValidationError::MissingReference(
+                    "cpm".to_string(),
+                    "sum_impressions".to_string()
+                ),
+                ValidationError::MissingAggregate("impressions".to_string()),
+                ValidationError::InvalidExpression(
+                    "campaign_id".to_string(),
+                    ParseError::UnexpectedEnd
+                ),
+                ValidationError::WindowReference(
+                    "cpm_of_cumulative".to_string(),
+                    "cumulative_cpm".to_string()
+                ),
+                ValidationError::InvalidMeasure(
+                    "share_of_campaign".to_string()
+                ),
+                ValidationError::InvalidTimezone("Europe/Berlin'".to_string()),
+                ValidationError::InvalidPolicy("sum_spend".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_unknown_data_type() {
+        let datasource: Datasource = serde_yml::from_str(
+            r#"
+name: default
+columns:
+  - name: T_CAMPAIGN_ID
+    column_id: campaign_id
+    expression: campaign_id
+    column_type: grouping
+    data_type: i32
+  - name: T_HOUR
+    column_id: hour
+    expression: ""
+    column_type: grouping
+    data_type: timestamp
+"#,
+        )
+        .expect("Unknown data types should parse");
+        assert_eq!(datasource.columns[0].data_type, DataType::I32);
+        assert_eq!(
+            datasource.validate(),
+            Err(vec![
+                ValidationError::UnknownDataType(
+                    "hour".to_string(),
+                    "timestamp".to_string()
+                ),
+                ValidationError::EmptyExpression("hour".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_column_references() {
+        let column = Column {
+            name: Arc::from("T_CPM"),
+            column_id: Arc::from("cpm"),
+            expression: Arc::from(
+                "coalesce(sum_spend, 0) / nullif(sum_impressions, 0) * 1000",
+            ),
+            column_type: ColumnType::Formula,
+            data_type: DataType::Dec64,
+            compatible_groupings: None,
+        };
+        assert_eq!(
+            column.references(),
+            vec![Arc::from("sum_spend"), Arc::from("sum_impressions")]
+        );
+
+        let column = Column {
+            expression: Arc::from("sum(fact_table.spend) + 'sum_spend'"),
+            ..column
+        };
+        assert!(column.references().is_empty());
+    }
+
+    #[test]
+    fn test_pivot_columns() {
+        let pivot = Pivot {
+            column: "date".to_string(),
+            values: vec!["2020-01-01".to_string(), "2020-02-01".to_string()],
+        };
+        let columns = pivot.columns(&["sum_impressions", "sum_spend"]);
+        assert_eq!(
+            columns
+                .iter()
+                .map(|c| c.column_id.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "sum_impressions_2020_01_01",
+                "sum_impressions_2020_02_01",
+                "sum_spend_2020_01_01",
+                "sum_spend_2020_02_01",
+            ]
+        );
+        assert_eq!(
+            columns[3],
+            PivotColumn {
+                column_id: "sum_spend_2020_02_01".to_string(),
+                aggregate: "sum_spend".to_string(),
+                value: "2020-02-01".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_literal() {
+        assert_eq!(DataType::I32.parse_literal("7"), Some(Literal::Integer(7)));
+        assert_eq!(DataType::I32.parse_literal("3000000000"), None);
+        assert_eq!(
+            DataType::I64.parse_literal("3000000000"),
+            Some(Literal::Integer(3000000000))
+        );
+        assert_eq!(
+            DataType::Dec64.parse_literal("-2.50"),
+            Some(Literal::Decimal(Arc::from("-2.50")))
+        );
+        assert_eq!(DataType::Dec64.parse_literal("2."), None);
+        assert_eq!(DataType::Dec64.parse_literal("1e3"), None);
+        assert_eq!(
+            DataType::Date.parse_literal("2020-02-29"),
+            Some(Literal::Date(Arc::from("2020-02-29")))
+        );
+        assert_eq!(DataType::Date.parse_literal("2021-02-29"), None);
+        assert_eq!(DataType::Date.parse_literal("2020-1-01"), None);
+        assert_eq!(
+            DataType::Text.parse_literal("it's"),
+            Some(Literal::Text(Arc::from("it's")))
+        );
+    }
+
+    #[test]
+    fn test_granularity_alignment() {
+        assert!(Granularity::Day.is_aligned("2020-01-15"));
+        assert!(Granularity::Week.is_aligned("2020-01-06"));
+        assert!(!Granularity::Week.is_aligned("2020-01-01"));
+        assert!(Granularity::Week.is_aligned("2000-02-28"));
+        assert!(Granularity::Month.is_aligned("2020-02-01"));
+        assert!(!Granularity::Month.is_aligned("2020-02-02"));
+        assert!(Granularity::Quarter.is_aligned("2020-10-01"));
+        assert!(!Granularity::Quarter.is_aligned("2020-11-01"));
+        assert!(Granularity::Year.is_aligned("2021-01-01"));
+        assert!(!Granularity::Year.is_aligned("2021-07-01"));
+        assert!(!Granularity::Day.is_aligned("2021-02-30"));
+    }
+
+    #[test]
+    fn test_granularity_contains() {
+        assert!(Granularity::Month.contains(Granularity::Day));
+        assert!(Granularity::Year.contains(Granularity::Quarter));
+        assert!(Granularity::Week.contains(Granularity::Day));
+        assert!(Granularity::Day.contains(Granularity::Day));
+        assert!(!Granularity::Month.contains(Granularity::Week));
+        assert!(!Granularity::Week.contains(Granularity::Month));
+        assert!(!Granularity::Hour.contains(Granularity::Day));
+        assert!(!Granularity::Month.contains(Granularity::Year));
+    }
+
+    #[test]
+    fn test_resolve_relative_filters() {
+        let today = Date::parse("2020-03-11").expect("Date should be valid");
+        let range = |range: &str| -> (String, String) {
+            let range: RelativeRange =
+                serde_json::from_str(&format!("\"{}\"", range))
+                    .expect("Could not parse range");
+            let (start, end) = range.bounds(today);
+            (start.to_string(), end.to_string())
+        };
+        let bounds = |start: &str, end: &str| (start.into(), end.into());
+        assert_eq!(range("today"), bounds("2020-03-11", "2020-03-12"));
+        assert_eq!(range("last_7_days"), bounds("2020-03-04", "2020-03-11"));
+        assert_eq!(range("week_to_date"), bounds("2020-03-09", "2020-03-12"));
+        assert_eq!(range("previous_week"), bounds("2020-03-02", "2020-03-09"));
+        assert_eq!(range("month_to_date"), bounds("2020-03-01", "2020-03-12"));
+        assert_eq!(range("previous_month"), bounds("2020-02-01", "2020-03-01"));
+        assert_eq!(
+            range("previous_quarter"),
+            bounds("2019-10-01", "2020-01-01")
+        );
+        assert_eq!(range("previous_year"), bounds("2019-01-01", "2020-01-01"));
+
+        let filters: Filter = serde_json::from_str(
+            r#"{"type": "and", "value": [
+                {"type": "relative", "column": "date", "range": "yesterday"},
+                {"type": "or", "value": [
+                    {"type": "relative", "column": "date", "range": "today"}
+                ]}
+            ]}"#,
+        )
+        .expect("Could not parse filters");
+        let gte = |value: &str| Filter::Gte {
+            column: "date".to_string(),
+            value: value.to_string(),
+        };
+        let lt = |value: &str| Filter::Lt {
+            column: "date".to_string(),
+            value: value.to_string(),
+        };
+        assert_eq!(
+            filters.resolve_relative(today),
+            Filter::And {
+                value: vec![
+                    gte("2020-03-10"),
+                    lt("2020-03-11"),
+                    Filter::Or {
+                        value: vec![Filter::And {
+                            value: vec![gte("2020-03-11"), lt("2020-03-12")]
+                        }]
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_deserialize_totals() {
+        let totals: Vec<Totals> = serde_json::from_str(
+            r#"["rollup", "cube", {"levels": [["campaign_id"], []]}]"#,
+        )
+        .expect("Could not parse totals");
+        assert_eq!(
+            totals,
+            vec![
+                Totals::Rollup,
+                Totals::Cube,
+                Totals::Levels(vec![vec!["campaign_id".to_string()], vec![]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_schema() {
+        let schema_file = "test/schema.yaml";
+        let schema: Schema =
+            load_yaml(schema_file).expect("Could not parse schema yaml");
+        assert_eq!(schema.name, Arc::from("default"));
+        assert_eq!(
+            schema.fact_table().map(|t| t.name.clone()),
+            Some(Arc::from("fact_table"))
+        );
+        assert_eq!(
+            schema.fact_table().and_then(|t| t.timestamp.clone()),
+            Some(Arc::from("ts"))
+        );
+        let hierarchies: Vec<&Table> = schema.hierarchies().collect();
+        assert_eq!(hierarchies.len(), 1);
+        assert_eq!(hierarchies[0].column, Some(Arc::from("line_item_id")));
+        let dimensions: Vec<&Table> = schema.dimensions().collect();
+        assert_eq!(dimensions.len(), 2);
+        assert_eq!(dimensions[0].column, Some(Arc::from("campaign_id")));
     }
 }
@@ -0,0 +1,144 @@
+use crate::domain::models::{Datasource, ValidationError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryError {
+    DuplicateDatasource(String),
+    InvalidDatasource(String, Vec<ValidationError>),
+    UnknownDatasource(String),
+    /// Request naming no datasource while several are registered.
+    AmbiguousDatasource(Vec<String>),
+}
+
+/// Validated datasources, looked up by name.
+pub struct DatasourceRegistry {
+    datasources: Vec<Datasource>,
+}
+
+impl DatasourceRegistry {
+    /// Validates every datasource and their names, reporting every problem
+    /// found.
+    pub fn new(
+        datasources: Vec<Datasource>,
+    ) -> Result<Self, Vec<RegistryError>> {
+        let mut errors = vec![];
+        for (index, datasource) in datasources.iter().enumerate() {
+            let name = datasource.name.to_string();
+            let duplicate = datasources[..index]
+                .iter()
+                .any(|d| d.name == datasource.name);
+            if duplicate
+                && !errors
+                    .contains(&RegistryError::DuplicateDatasource(name.clone()))
+            {
+                errors.push(RegistryError::DuplicateDatasource(name.clone()));
+            }
+            if let Err(invalid) = datasource.validate() {
+                errors.push(RegistryError::InvalidDatasource(name, invalid));
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(DatasourceRegistry { datasources }),
+            false => Err(errors),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Datasource, RegistryError> {
+        self.datasources
+            .iter()
+            .find(|d| d.name.as_ref() == name)
+            .ok_or(RegistryError::UnknownDatasource(name.to_string()))
+    }
+
+    /// Datasource a request names, or the only registered one when it
+    /// names none.
+    pub fn resolve(
+        &self,
+        name: Option<&str>,
+    ) -> Result<&Datasource, RegistryError> {
+        match (name, self.datasources.as_slice()) {
+            (Some(name), _) => self.get(name),
+            (None, [datasource]) => Ok(datasource),
+            (None, datasources) => Err(RegistryError::AmbiguousDatasource(
+                datasources.iter().map(|d| d.name.to_string()).collect(),
+            )),
+        }
+    }
+
+    pub fn datasources(&self) -> &[Datasource] {
+        &self.datasources
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::{Column, ColumnType, DataType};
+    use std::sync::Arc;
+
+    fn datasource(name: &str, expression: &str) -> Datasource {
+        Datasource {
+            name: Arc::from(name),
+            columns: vec![Column {
+                name: Arc::from("DATE"),
+                column_id: Arc::from("date"),
+                expression: Arc::from(expression),
+                column_type: ColumnType::Grouping,
+                data_type: DataType::Date,
+                compatible_groupings: None,
+            }],
+            timezone: None,
+            policies: vec![],
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let registry = DatasourceRegistry::new(vec![
+            datasource("display", "fact_table.date"),
+            datasource("video", "video_facts.date"),
+        ])
+        .expect("Registry should be valid");
+
+        let video = registry.resolve(Some("video")).expect("Should resolve");
+        assert_eq!(video.name, Arc::from("video"));
+        assert_eq!(
+            registry.resolve(Some("audio")).err(),
+            Some(RegistryError::UnknownDatasource("audio".to_string()))
+        );
+        assert_eq!(
+            registry.resolve(None).err(),
+            Some(RegistryError::AmbiguousDatasource(vec![
+                "display".to_string(),
+                "video".to_string()
+            ]))
+        );
+
+        let registry =
+            DatasourceRegistry::new(vec![datasource("display", "date")])
+                .expect("Registry should be valid");
+        let display = registry.resolve(None).expect("Should resolve");
+        assert_eq!(display.name, Arc::from("display"));
+    }
+
+    #[test]
+    fn test_new_reports_all_errors() {
+        let errors = DatasourceRegistry::new(vec![
+            datasource("display", "fact_table.date"),
+            datasource("display", "fact_table.date"),
+            datasource("video", ""),
+        ])
+        .err()
+        .expect("Registry should be invalid");
+
+        assert_eq!(
+            errors,
+            vec![
+                RegistryError::DuplicateDatasource("display".to_string()),
+                RegistryError::InvalidDatasource(
+                    "video".to_string(),
+                    vec![ValidationError::EmptyExpression("date".to_string())]
+                ),
+            ]
+        );
+    }
+}
@@ -1,38 +1,77 @@
-use crate::domain::models::{
-    Column, Datasource, Report, ReportRequest, ReportStatus,
-};
-use std::rc::Rc;
+use crate::domain::calendar::{Clock, SystemClock};
+use crate::domain::models::{Report, ReportRequest, ReportStatus};
+use crate::domain::registry::{DatasourceRegistry, RegistryError};
+use std::sync::Arc;
 use uuid::Uuid;
 
-enum Error {}
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServiceError {
+    Datasource(RegistryError),
+    /// Report timezone the clock cannot tell the current date in.
+    InvalidTimezone(String),
+}
 
 pub struct ReportService {
-    datasource: Datasource,
+    registry: DatasourceRegistry,
+    clock: Box<dyn Clock>,
 }
 
 impl ReportService {
-    pub fn new(datasource: Datasource) -> Self {
-        ReportService { datasource }
+    pub fn new(registry: DatasourceRegistry) -> Self {
+        ReportService {
+            registry,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(self, clock: Box<dyn Clock>) -> Self {
+        ReportService { clock, ..self }
     }
 
-    pub fn create_report(&self, request: ReportRequest) -> Report {
-        let id: Rc<str> = Uuid::new_v4().to_string().into_boxed_str().into();
+    /// Creates a pending report on the datasource the request names.
+    pub fn create_report(
+        &self,
+        request: ReportRequest,
+    ) -> Result<Report, ServiceError> {
+        let datasource = self
+            .registry
+            .resolve(request.datasource.as_deref())
+            .map_err(ServiceError::Datasource)?;
+        let pivot_columns = datasource.pivot_columns(&request);
+        let id: Arc<str> = Uuid::new_v4().to_string().into_boxed_str().into();
 
+        let resolved_filters = datasource
+            .resolve_filters(&request, self.clock.as_ref())
+            .ok_or_else(|| {
+                ServiceError::InvalidTimezone(
+                    datasource
+                        .report_timezone(&request)
+                        .unwrap_or("UTC")
+                        .to_string(),
+                )
+            })?;
         let status = ReportStatus::Pending;
         let metadata = None;
-        let report = Report {
+        Ok(Report {
             id,
             request,
+            resolved_filters: Some(resolved_filters),
+            pivot_columns,
             status,
             metadata,
-        };
-        report
+        })
+    }
+
+    pub fn registry(&self) -> &DatasourceRegistry {
+        &self.registry
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::calendar::{Date, FixedClock};
+    use crate::domain::models::{Datasource, Filter, Pivot, PivotColumn};
     use crate::domain::tests::{load_json, load_yaml};
 
     #[test]
@@ -42,13 +81,105 @@ mod tests {
         let request: ReportRequest =
             load_json(request_file).expect("Could not parse request json");
 
-        let datasource_file = "test/datasource.yaml";
+        let datasource_file = "test/datasources/default.yaml";
         let datasource: Datasource =
             load_yaml(datasource_file).expect("Could not parse request yaml");
 
-        let report_service = ReportService { datasource };
-        let report = report_service.create_report(request);
+        let registry = DatasourceRegistry::new(vec![datasource])
+            .expect("Datasource should be valid");
+        let report_service = ReportService::new(registry);
+        let report = report_service
+            .create_report(request.clone())
+            .expect("Report should be created");
 
         assert_eq!(report.status, ReportStatus::Pending);
+        assert!(report.pivot_columns.is_empty());
+
+        let pivoted = ReportRequest {
+            columns: vec!["campaign_id".to_string(), "sum_spend".to_string()],
+            pivot: Some(Pivot {
+                column: "campaign_id".to_string(),
+                values: vec!["7".to_string()],
+            }),
+            ..request.clone()
+        };
+        let report = report_service
+            .create_report(pivoted)
+            .expect("Report should be created");
+        assert_eq!(
+            report.pivot_columns,
+            vec![PivotColumn {
+                column_id: "sum_spend_7".to_string(),
+                aggregate: "sum_spend".to_string(),
+                value: "7".to_string(),
+            }]
+        );
+
+        let request = ReportRequest {
+            datasource: Some("missing".to_string()),
+            ..request
+        };
+        assert_eq!(
+            report_service.create_report(request).err(),
+            Some(ServiceError::Datasource(RegistryError::UnknownDatasource(
+                "missing".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn test_create_report_records_resolved_filters() {
+        let request: ReportRequest = serde_json::from_str(
+            r#"{
+                "columns": ["date"],
+                "filters": {"type": "and", "value": [
+                    {"type": "relative", "column": "date", "range": "previous_month"}
+                ]},
+                "sort": []
+            }"#,
+        )
+        .expect("Could not parse request json");
+        let datasource: Datasource = load_yaml("test/datasources/default.yaml")
+            .expect("Could not parse datasource yaml");
+        let today = Date::parse("2020-03-11").expect("Date should be valid");
+        let registry = DatasourceRegistry::new(vec![datasource])
+            .expect("Datasource should be valid");
+        let report_service = ReportService::new(registry)
+            .with_clock(Box::new(FixedClock(today)));
+
+        let report = report_service
+            .create_report(request.clone())
+            .expect("Report should be created");
+        assert_eq!(
+            report.resolved_filters,
+            Some(Filter::And {
+                value: vec![
+                    Filter::Gte {
+                        column: "date".to_string(),
+                        value: "2020-02-01".to_string(),
+                    },
+                    Filter::Lt {
+                        column: "date".to_string(),
+                        value: "2020-03-01".to_string(),
+                    },
+                ]
+            })
+        );
+
+        // The system clock knows no date in unknown timezones.
+        let request = ReportRequest {
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+            ..request
+        };
+        let report_service = ReportService {
+            clock: Box::new(SystemClock),
+            ..report_service
+        };
+        assert_eq!(
+            report_service.create_report(request).err(),
+            Some(ServiceError::InvalidTimezone(
+                "Mars/Olympus_Mons".to_string()
+            ))
+        );
     }
 }
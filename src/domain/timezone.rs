@@ -0,0 +1,414 @@
+use crate::domain::calendar::Date;
+use std::fs;
+use std::path::Path;
+
+/// Directory of the system zone database, one TZif file per zone.
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Offset from UTC, in seconds, of a timezone at the Unix time `instant`.
+/// Zones are UTC offsets such as `+05:30`, or zones of the system zone
+/// database such as `Europe/Berlin`; `None` for unknown zones.
+pub fn utc_offset(zone: &str, instant: i64) -> Option<i64> {
+    if let Some(offset) = fixed_offset(zone) {
+        return Some(offset);
+    }
+    // Zone names are paths relative to the zone database.
+    if zone
+        .split('/')
+        .any(|part| part.is_empty() || part.starts_with('.'))
+    {
+        return None;
+    }
+    let data = fs::read(Path::new(ZONEINFO_DIR).join(zone)).ok()?;
+    tzif_offset(&data, instant)
+}
+
+/// Offset of a `+HH`, `+HHMM` or `+HH:MM` zone, or of its `-` counterpart.
+fn fixed_offset(zone: &str) -> Option<i64> {
+    let sign = match zone.bytes().next()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let rest = &zone[1..];
+    let (hours, minutes) = match rest.len() {
+        2 => (rest, "00"),
+        4 => rest.split_at(2),
+        5 if rest.as_bytes()[2] == b':' => (&rest[..2], &rest[3..]),
+        _ => return None,
+    };
+    if !hours
+        .bytes()
+        .chain(minutes.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let hours: i64 = hours.parse().ok()?;
+    let minutes: i64 = minutes.parse().ok()?;
+    (hours <= 24 && minutes < 60).then_some(sign * (hours * 60 + minutes) * 60)
+}
+
+/// Counts of the header of a TZif file (RFC 8536).
+struct Header {
+    version: u8,
+    isut: usize,
+    isstd: usize,
+    leap: usize,
+    time: usize,
+    types: usize,
+    chars: usize,
+}
+
+const HEADER_LEN: usize = 44;
+
+impl Header {
+    fn parse(data: &[u8]) -> Option<Header> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let count = |index: usize| -> Option<usize> {
+            let start = 20 + 4 * index;
+            let bytes = data.get(start..start + 4)?.try_into().ok()?;
+            Some(u32::from_be_bytes(bytes) as usize)
+        };
+        Some(Header {
+            version: *data.get(4)?,
+            isut: count(0)?,
+            isstd: count(1)?,
+            leap: count(2)?,
+            time: count(3)?,
+            types: count(4)?,
+            chars: count(5)?,
+        })
+    }
+
+    /// Length of the data block following the header, with transition
+    /// times of `size` bytes.
+    fn block_len(&self, size: usize) -> usize {
+        self.time * (size + 1)
+            + self.types * 6
+            + self.chars
+            + self.leap * (size + 4)
+            + self.isstd
+            + self.isut
+    }
+}
+
+/// Offset of the local time type of the last transition at or before
+/// `instant`, or of the footer rule after the last transition.
+fn tzif_offset(data: &[u8], instant: i64) -> Option<i64> {
+    let first = Header::parse(data)?;
+    // Version 2 files repeat the data with 64-bit times, then a footer.
+    let (header, block, size) = match first.version {
+        0 => (first, data.get(HEADER_LEN..)?, 4),
+        _ => {
+            let second = data.get(HEADER_LEN + first.block_len(4)..)?;
+            (Header::parse(second)?, second.get(HEADER_LEN..)?, 8)
+        }
+    };
+    let times_len = header.time * size;
+    let transitions: Vec<i64> = block
+        .get(..times_len)?
+        .chunks(size)
+        .map(|bytes| {
+            bytes
+                .iter()
+                .fold(if bytes[0] >= 0x80 { -1 } else { 0 }, |time, b| {
+                    time << 8 | *b as i64
+                })
+        })
+        .collect();
+    let indices = block.get(times_len..times_len + header.time)?;
+    let types = block.get(times_len + header.time..)?;
+    let type_offset = |index: usize| -> Option<i64> {
+        let bytes = types.get(index * 6..index * 6 + 4)?.try_into().ok()?;
+        Some(i32::from_be_bytes(bytes) as i64)
+    };
+
+    let last = transitions.iter().rposition(|time| *time <= instant);
+    if size == 8
+        && last.map_or(transitions.is_empty(), |i| i + 1 == transitions.len())
+    {
+        let footer = block.get(header.block_len(8)..)?;
+        let rule = footer
+            .strip_prefix(b"\n")
+            .and_then(|f| f.split(|b| *b == b'\n').next())
+            .and_then(|rule| std::str::from_utf8(rule).ok())
+            .and_then(Rule::parse);
+        if let Some(rule) = rule {
+            return Some(rule.offset(instant));
+        }
+    }
+    match last {
+        Some(index) => type_offset(*indices.get(index)? as usize),
+        None => type_offset(0),
+    }
+}
+
+/// POSIX TZ rule, such as `CET-1CEST,M3.5.0,M10.5.0/3`, with offsets east
+/// of UTC.
+#[derive(Debug, PartialEq)]
+struct Rule {
+    standard: i64,
+    daylight: Option<Daylight>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Daylight {
+    offset: i64,
+    start: Transition,
+    end: Transition,
+}
+
+/// Local day and time of a daylight saving transition.
+#[derive(Debug, PartialEq)]
+struct Transition {
+    day: TransitionDay,
+    time: i64,
+}
+
+#[derive(Debug, PartialEq)]
+enum TransitionDay {
+    /// `Jn`: day 1 to 365 of the year, never counting February 29.
+    Julian(i64),
+    /// `n`: day 0 to 365 of the year.
+    Ordinal(i64),
+    /// `Mm.w.d`: day `d` of the week, 0 for Sunday, in week `w` of month
+    /// `m`, week 5 being the last.
+    Month(u32, u32, u32),
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Option<Rule> {
+        let mut rest = rule;
+        zone_name(&mut rest)?;
+        let standard = -offset(&mut rest)?;
+        if rest.is_empty() {
+            return Some(Rule {
+                standard,
+                daylight: None,
+            });
+        }
+        zone_name(&mut rest)?;
+        let daylight_offset = if rest.starts_with(',') {
+            standard + 3600
+        } else {
+            -offset(&mut rest)?
+        };
+        // Zones without transition rules are left to the transitions.
+        rest = rest.strip_prefix(',')?;
+        let start = transition(&mut rest)?;
+        rest = rest.strip_prefix(',')?;
+        let end = transition(&mut rest)?;
+        rest.is_empty().then_some(Rule {
+            standard,
+            daylight: Some(Daylight {
+                offset: daylight_offset,
+                start,
+                end,
+            }),
+        })
+    }
+
+    fn offset(&self, instant: i64) -> i64 {
+        let Some(daylight) = &self.daylight else {
+            return self.standard;
+        };
+        let year = Date::from_days_since_epoch(
+            (instant + self.standard).div_euclid(SECONDS_PER_DAY),
+        )
+        .year();
+        // Transitions are in the local time in effect before them.
+        let start = daylight.start.local_time(year) - self.standard;
+        let end = daylight.end.local_time(year) - daylight.offset;
+        let in_daylight = if start < end {
+            start <= instant && instant < end
+        } else {
+            !(end <= instant && instant < start)
+        };
+        if in_daylight {
+            daylight.offset
+        } else {
+            self.standard
+        }
+    }
+}
+
+impl Transition {
+    /// Local time of the transition in `year`, in seconds since the epoch.
+    fn local_time(&self, year: i32) -> i64 {
+        let first = Date::new(year, 1, 1).expect("January 1 is a date");
+        let leap = Date::new(year, 2, 29).is_some();
+        let date = match self.day {
+            TransitionDay::Julian(day) => {
+                first.add_days(day - 1 + (leap && day >= 60) as i64)
+            }
+            TransitionDay::Ordinal(day) => first.add_days(day),
+            TransitionDay::Month(month, week, weekday) => {
+                let start =
+                    Date::new(year, month, 1).expect("Rule months are valid");
+                // Date weekdays start on Monday, POSIX ones on Sunday.
+                let first_weekday = (start.weekday() + 1) % 7;
+                let mut day = 1 + (weekday + 7 - first_weekday) % 7;
+                day += (week - 1) * 7;
+                while day > 28 && Date::new(year, month, day).is_none() {
+                    day -= 7;
+                }
+                start.add_days(day as i64 - 1)
+            }
+        };
+        date.days_since_epoch() * SECONDS_PER_DAY + self.time
+    }
+}
+
+/// Skips a zone abbreviation, alphabetic or quoted in angle brackets.
+fn zone_name(rest: &mut &str) -> Option<()> {
+    let len = match rest.strip_prefix('<') {
+        Some(quoted) => quoted.find('>')? + 2,
+        None => rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len()),
+    };
+    *rest = &rest[len..];
+    (len >= 3).then_some(())
+}
+
+/// `[+-]hh[:mm[:ss]]` in seconds, positive west of UTC in zone offsets.
+fn offset(rest: &mut &str) -> Option<i64> {
+    let sign = if let Some(stripped) = rest.strip_prefix('-') {
+        *rest = stripped;
+        -1
+    } else {
+        *rest = rest.strip_prefix('+').unwrap_or(rest);
+        1
+    };
+    let mut seconds = 0;
+    for (index, unit) in [3600, 60, 1].into_iter().enumerate() {
+        if index > 0 {
+            match rest.strip_prefix(':') {
+                Some(stripped) => *rest = stripped,
+                None => break,
+            }
+        }
+        seconds += number(rest)? * unit;
+    }
+    Some(sign * seconds)
+}
+
+/// `date[/time]` of a transition, at 02:00 unless a time is given.
+fn transition(rest: &mut &str) -> Option<Transition> {
+    let day = if let Some(stripped) = rest.strip_prefix('M') {
+        *rest = stripped;
+        let month = number(rest)?;
+        *rest = rest.strip_prefix('.')?;
+        let week = number(rest)?;
+        *rest = rest.strip_prefix('.')?;
+        let weekday = number(rest)?;
+        if !(1..=12).contains(&month)
+            || !(1..=5).contains(&week)
+            || !(0..=6).contains(&weekday)
+        {
+            return None;
+        }
+        TransitionDay::Month(month as u32, week as u32, weekday as u32)
+    } else if let Some(stripped) = rest.strip_prefix('J') {
+        *rest = stripped;
+        TransitionDay::Julian(number(rest)?.clamp(1, 365))
+    } else {
+        TransitionDay::Ordinal(number(rest)?.clamp(0, 365))
+    };
+    let time = match rest.strip_prefix('/') {
+        Some(stripped) => {
+            *rest = stripped;
+            offset(rest)?
+        }
+        None => 7200,
+    };
+    Some(Transition { day, time })
+}
+
+fn number(rest: &mut &str) -> Option<i64> {
+    let len = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let value = rest[..len].parse().ok()?;
+    *rest = &rest[len..];
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unix time of midnight UTC starting a date.
+    fn midnight(value: &str) -> i64 {
+        let date = Date::parse(value).expect("Date should be valid");
+        date.days_since_epoch() * SECONDS_PER_DAY
+    }
+
+    #[test]
+    fn test_fixed_offsets() {
+        assert_eq!(utc_offset("+05:30", 0), Some(19800));
+        assert_eq!(utc_offset("-0800", 0), Some(-28800));
+        assert_eq!(utc_offset("+02", 0), Some(7200));
+        assert_eq!(utc_offset("+2:00", 0), None);
+        assert_eq!(utc_offset("/etc/passwd", 0), None);
+        assert_eq!(utc_offset("../zoneinfo/UTC", 0), None);
+    }
+
+    #[test]
+    fn test_rule_offsets() {
+        let berlin =
+            Rule::parse("CET-1CEST,M3.5.0,M10.5.0/3").expect("Rule is valid");
+        assert_eq!(berlin.offset(midnight("2020-01-15")), 3600);
+        assert_eq!(berlin.offset(midnight("2020-07-15")), 7200);
+        // Daylight saving time started at 01:00 UTC on 2020-03-29.
+        assert_eq!(berlin.offset(midnight("2020-03-29") + 3599), 3600);
+        assert_eq!(berlin.offset(midnight("2020-03-29") + 3600), 7200);
+        // And ended at 01:00 UTC on 2020-10-25.
+        assert_eq!(berlin.offset(midnight("2020-10-25") + 3599), 7200);
+        assert_eq!(berlin.offset(midnight("2020-10-25") + 3600), 3600);
+
+        let sydney =
+            Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").expect("Rule is valid");
+        assert_eq!(sydney.offset(midnight("2020-01-15")), 39600);
+        assert_eq!(sydney.offset(midnight("2020-07-15")), 36000);
+
+        let india = Rule::parse("IST-5:30").expect("Rule is valid");
+        assert_eq!(india.offset(0), 19800);
+        let quoted = Rule::parse("<-03>3").expect("Rule is valid");
+        assert_eq!(quoted.offset(0), -10800);
+        assert_eq!(Rule::parse("EST5EDT"), None);
+    }
+
+    #[test]
+    fn test_tzif_offsets() {
+        // Version 2 file with a single transition to CET in 1970 and the
+        // Berlin rule after it.
+        let header = |time: u32, types: u32, chars: u32| {
+            let mut header = b"TZif2".to_vec();
+            header.extend([0; 15]);
+            for count in [0, 0, 0, time, types, chars] {
+                header.extend(u32::to_be_bytes(count));
+            }
+            header
+        };
+        let mut data = header(0, 1, 4);
+        data.extend([0, 0, 0x0e, 0x10, 0, 0]);
+        data.extend(b"CET\0");
+        data.extend(header(1, 2, 8));
+        data.extend(i64::to_be_bytes(-86400));
+        data.push(1);
+        data.extend([0, 0, 0, 0, 0, 0]);
+        data.extend([0, 0, 0x0e, 0x10, 0, 4]);
+        data.extend(b"UTC\0CET\0");
+        data.extend(b"\nCET-1CEST,M3.5.0,M10.5.0/3\n");
+
+        assert_eq!(tzif_offset(&data, -86401), Some(0));
+        assert_eq!(tzif_offset(&data, -86400), Some(3600));
+        assert_eq!(tzif_offset(&data, midnight("2020-07-15")), Some(7200));
+        assert_eq!(tzif_offset(b"TZif", 0), None);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::domain::models::{ColumnType, DataType, Filter, PivotColumn};
+use crate::executor::query::{SQLGenerator, SqlAst};
+use serde::Serialize;
+
+/// What the planner made of a report request: the columns it resolved,
+/// the tables it reads and the SQL it generated.
+#[derive(Serialize, Debug)]
+pub struct Explain {
+    pub columns: Vec<ResolvedColumn>,
+    /// Tables the query reads, in the order they appear in the SQL.
+    pub tables: Vec<String>,
+    pub joins: Vec<ExplainJoin>,
+    pub filters: FilterSplit,
+    /// Output columns generated by the pivot of the request.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pivot_columns: Vec<PivotColumn>,
+    pub sql: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ResolvedColumn {
+    pub column_id: String,
+    pub column_type: ColumnType,
+    pub data_type: DataType,
+    /// Parsed expression of the column, as it is rendered into SQL.
+    pub expression: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ExplainJoin {
+    pub join_type: String,
+    /// Joined table, or the alias of the joined subquery.
+    pub table: String,
+    pub on: String,
+}
+
+/// Top level filters the plan applies, security policies included and date
+/// bounds as they are compared, by the clause they are compiled into.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct FilterSplit {
+    /// Filters on grouping columns, applied before aggregation.
+    pub where_clause: Vec<Filter>,
+    /// Filters on aggregates, applied after aggregation.
+    pub having: Vec<Filter>,
+    /// Filters on formulas, and on the aggregates of drill-across reports,
+    /// applied over the aggregated facts.
+    pub outer_where: Vec<Filter>,
+}
+
+/// Names of the tables read by the query, without duplicates.
+pub(crate) fn tables(ast: &SqlAst) -> Vec<String> {
+    let mut tables = vec![];
+    collect_tables(ast, &mut tables);
+    tables
+}
+
+fn collect_tables(ast: &SqlAst, tables: &mut Vec<String>) {
+    if let SqlAst::Table(name, _) = ast {
+        if !tables.iter().any(|t| t == name.as_ref()) {
+            tables.push(name.to_string());
+        }
+    }
+    for child in ast.children() {
+        collect_tables(child, tables);
+    }
+}
+
+/// Joins of the query, in the order they appear in the SQL.
+pub(crate) fn joins(ast: &SqlAst) -> Vec<ExplainJoin> {
+    let mut joins = vec![];
+    collect_joins(ast, &mut joins);
+    joins
+}
+
+fn collect_joins(ast: &SqlAst, joins: &mut Vec<ExplainJoin>) {
+    let SqlAst::Join {
+        left,
+        right,
+        join_type,
+        on,
+    } = ast
+    else {
+        for child in ast.children() {
+            collect_joins(child, joins);
+        }
+        return;
+    };
+    collect_joins(left, joins);
+    let table = match right.as_ref() {
+        SqlAst::Table(name, _) => name.to_string(),
+        SqlAst::Subquery(_, alias) => alias.to_string(),
+        other => SQLGenerator::new().generate_sql(other).trim().to_string(),
+    };
+    joins.push(ExplainJoin {
+        join_type: join_type.keyword().to_string(),
+        table,
+        on: SQLGenerator::new().generate_sql(on).trim().to_string(),
+    });
+    collect_joins(right, joins);
+    collect_joins(on, joins);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arc;
+    use crate::executor::query::{JoinType, Operator};
+    use std::sync::Arc;
+
+    fn join(
+        left: SqlAst,
+        right: SqlAst,
+        join_type: JoinType,
+        keys: (&str, &str),
+    ) -> SqlAst {
+        SqlAst::Join {
+            left: Box::new(left),
+            right: Box::new(right),
+            join_type,
+            on: Box::new(SqlAst::Comparison {
+                left: Box::new(SqlAst::Column(keys.0.into())),
+                operator: Operator::Equal,
+                right: Box::new(SqlAst::Column(keys.1.into())),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_tables_and_joins_in_sql_order() {
+        let facts = SqlAst::Subquery(
+            Box::new(SqlAst::Select {
+                columns: vec![],
+                from: Box::new(join(
+                    SqlAst::Table(arc!["fact_table"], arc!["fact_table"]),
+                    SqlAst::Table(arc!["hierarchy"], arc!["hierarchy"]),
+                    JoinType::Left,
+                    ("fact_table.id", "hierarchy.id"),
+                )),
+                where_clause: None,
+                group_by: None,
+                having: None,
+                order_by: None,
+            }),
+            arc!["facts"],
+        );
+        let ast = join(
+            facts,
+            SqlAst::Table(arc!["dimension"], arc!["dimension"]),
+            JoinType::Inner,
+            ("facts.id", "dimension.id"),
+        );
+
+        assert_eq!(tables(&ast), vec!["fact_table", "hierarchy", "dimension"]);
+        assert_eq!(
+            joins(&ast),
+            vec![
+                ExplainJoin {
+                    join_type: "LEFT JOIN".to_string(),
+                    table: "hierarchy".to_string(),
+                    on: "fact_table.id = hierarchy.id".to_string(),
+                },
+                ExplainJoin {
+                    join_type: "INNER JOIN".to_string(),
+                    table: "dimension".to_string(),
+                    on: "facts.id = dimension.id".to_string(),
+                },
+            ]
+        );
+    }
+}
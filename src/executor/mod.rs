@@ -1,10 +1,12 @@
+pub mod explain;
+pub mod optimizer;
 pub mod planner;
 pub mod query;
 
 
 #[macro_export]
-macro_rules! rc {
+macro_rules! arc {
     ($str:literal) => {
-        Rc::from($str)
+        Arc::from($str)
     };
 }
@@ -0,0 +1,660 @@
+use crate::domain::expression::{BinaryOperator, Expression};
+use crate::domain::models::Literal;
+use crate::executor::query::{LogicalVariant, Operator, SqlAst};
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// Rewrite of a single node, or `None` when the pass does not apply to it.
+pub type Pass = fn(&SqlAst) -> Option<SqlAst>;
+
+/// Rounds of passes after which optimization stops, even if a pass would
+/// still change the tree.
+const MAX_ROUNDS: usize = 16;
+
+/// Runs rewrite passes over every node of a query, children first, until
+/// none of them changes the query.
+pub struct Optimizer {
+    passes: Vec<Pass>,
+}
+
+impl Optimizer {
+    pub fn new(passes: Vec<Pass>) -> Self {
+        Optimizer { passes }
+    }
+
+    pub fn optimize(&self, ast: SqlAst) -> SqlAst {
+        let mut ast = ast;
+        for _ in 0..MAX_ROUNDS {
+            let optimized = self
+                .passes
+                .iter()
+                .fold(ast.clone(), |ast, pass| rewrite(&ast, *pass));
+            if optimized == ast {
+                break;
+            }
+            ast = optimized;
+        }
+        ast
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Optimizer::new(vec![
+            flatten_logical,
+            fold_constants,
+            push_down_predicates,
+            remove_redundant_subqueries,
+        ])
+    }
+}
+
+/// Applies `pass` to every node of the tree, children first.
+fn rewrite(ast: &SqlAst, pass: Pass) -> SqlAst {
+    let rebuilt = ast.transform(&|node| match std::ptr::eq(node, ast) {
+        true => None,
+        false => Some(rewrite(node, pass)),
+    });
+    pass(&rebuilt).unwrap_or(rebuilt)
+}
+
+/// Splices AND items of an AND, and OR items of an OR, into their parent
+/// and unwraps single item ANDs and ORs.
+pub fn flatten_logical(ast: &SqlAst) -> Option<SqlAst> {
+    let SqlAst::Logical { items, variant } = ast else {
+        return None;
+    };
+    if let [item] = items.as_slice() {
+        return Some(item.clone());
+    }
+    let nested = |item: &SqlAst| match item {
+        SqlAst::Logical { variant: v, .. } => v == variant,
+        _ => false,
+    };
+    if !items.iter().any(nested) {
+        return None;
+    }
+    let mut flattened = vec![];
+    for item in items.iter() {
+        match item {
+            SqlAst::Logical { items, .. } if nested(item) => {
+                flattened.extend(items.iter().cloned())
+            }
+            other => flattened.push(other.clone()),
+        }
+    }
+    Some(SqlAst::Logical {
+        items: flattened,
+        variant: *variant,
+    })
+}
+
+/// Evaluates comparisons of literals and integer arithmetic on numbers,
+/// then simplifies ANDs, ORs, WHERE and HAVING over the constants.
+pub fn fold_constants(ast: &SqlAst) -> Option<SqlAst> {
+    match ast {
+        SqlAst::Comparison {
+            left,
+            operator,
+            right,
+        } => match (left.as_ref(), right.as_ref()) {
+            (SqlAst::Literal(left), SqlAst::Literal(right)) => {
+                compare(left, *operator, right).map(SqlAst::Boolean)
+            }
+            _ => None,
+        },
+        SqlAst::Logical { items, variant } => {
+            // FALSE decides an AND on its own, and TRUE an OR.
+            let decisive = *variant == LogicalVariant::Or;
+            if items.contains(&SqlAst::Boolean(decisive)) {
+                return Some(SqlAst::Boolean(decisive));
+            }
+            let neutral = SqlAst::Boolean(!decisive);
+            if !items.is_empty() && !items.contains(&neutral) {
+                return None;
+            }
+            let remaining: Vec<SqlAst> =
+                items.iter().filter(|i| **i != neutral).cloned().collect();
+            Some(match remaining.is_empty() {
+                true => neutral,
+                false => SqlAst::Logical {
+                    items: remaining,
+                    variant: *variant,
+                },
+            })
+        }
+        SqlAst::Select {
+            columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            order_by,
+        } => {
+            let always = |clause: &Option<Box<SqlAst>>| {
+                clause.as_deref() == Some(&SqlAst::Boolean(true))
+            };
+            if !always(where_clause) && !always(having) {
+                return None;
+            }
+            let kept = |clause: &Option<Box<SqlAst>>| {
+                clause.clone().filter(|_| !always(clause))
+            };
+            Some(SqlAst::Select {
+                columns: columns.clone(),
+                from: from.clone(),
+                where_clause: kept(where_clause),
+                group_by: group_by.clone(),
+                having: kept(having),
+                order_by: order_by.clone(),
+            })
+        }
+        SqlAst::Scalar(expression) => {
+            fold_arithmetic(expression).map(SqlAst::Scalar)
+        }
+        SqlAst::ScalarAlias { expression, alias } => {
+            fold_arithmetic(expression).map(|expression| SqlAst::ScalarAlias {
+                expression,
+                alias: alias.clone(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Outcome of comparing two literals, when it does not depend on the
+/// database. Text is only compared for equality, as its order depends on
+/// the collation.
+fn compare(
+    left: &Literal,
+    operator: Operator,
+    right: &Literal,
+) -> Option<bool> {
+    let ordering = match (left, right) {
+        (Literal::Integer(left), Literal::Integer(right)) => left.cmp(right),
+        // `YYYY-MM-DD` dates sort like their text.
+        (Literal::Date(left), Literal::Date(right)) => left.cmp(right),
+        (Literal::Text(left), Literal::Text(right))
+            if matches!(operator, Operator::Equal | Operator::NotEqual) =>
+        {
+            left.cmp(right)
+        }
+        _ => return None,
+    };
+    match operator {
+        Operator::Equal => Some(ordering == Ordering::Equal),
+        Operator::NotEqual => Some(ordering != Ordering::Equal),
+        Operator::Less => Some(ordering == Ordering::Less),
+        Operator::Greater => Some(ordering == Ordering::Greater),
+        Operator::LessOrEqual => Some(ordering != Ordering::Greater),
+        Operator::GreaterOrEqual => Some(ordering != Ordering::Less),
+        Operator::In => None,
+    }
+}
+
+/// Folds addition, subtraction and multiplication of integers with a
+/// non-negative result. Division and decimals are left to the database,
+/// which knows their precision.
+fn fold_arithmetic(expression: &Expression) -> Option<Expression> {
+    let folded = expression.transform(&|e| {
+        let Expression::Binary {
+            left,
+            operator,
+            right,
+        } = e
+        else {
+            return None;
+        };
+        let (Expression::Number(left), Expression::Number(right)) =
+            (left.as_ref(), right.as_ref())
+        else {
+            return None;
+        };
+        let (left, right) =
+            (left.parse::<i64>().ok()?, right.parse::<i64>().ok()?);
+        let value = match operator {
+            BinaryOperator::Add => left.checked_add(right),
+            BinaryOperator::Subtract => left.checked_sub(right),
+            BinaryOperator::Multiply => left.checked_mul(right),
+            BinaryOperator::Divide => None,
+        }
+        .filter(|value| *value >= 0)?;
+        Some(Expression::Number(value.to_string().into()))
+    });
+    (folded != *expression).then_some(folded)
+}
+
+/// Moves the conjuncts of a WHERE over a subquery into the WHERE of the
+/// subquery, when they only read subquery columns known before its
+/// aggregation: plain GROUP BY expressions, or any column of a subquery
+/// that does not aggregate.
+pub fn push_down_predicates(ast: &SqlAst) -> Option<SqlAst> {
+    let SqlAst::Select {
+        columns,
+        from,
+        where_clause: Some(predicate),
+        group_by,
+        having,
+        order_by,
+    } = ast
+    else {
+        return None;
+    };
+    let SqlAst::Subquery(subquery, alias) = from.as_ref() else {
+        return None;
+    };
+    let SqlAst::Select {
+        columns: inner_columns,
+        from: inner_from,
+        where_clause: inner_where,
+        group_by: inner_group_by,
+        having: inner_having,
+        order_by: inner_order_by,
+    } = subquery.as_ref()
+    else {
+        return None;
+    };
+    let conjuncts = match predicate.as_ref() {
+        SqlAst::Logical {
+            items,
+            variant: LogicalVariant::And,
+        } => items.clone(),
+        other => vec![other.clone()],
+    };
+    let mut pushed = vec![];
+    let mut kept = vec![];
+    for conjunct in conjuncts {
+        match push_down(
+            &conjunct,
+            alias,
+            inner_columns,
+            inner_group_by.as_deref(),
+        ) {
+            Some(rewritten) => pushed.push(rewritten),
+            None => kept.push(conjunct),
+        }
+    }
+    if pushed.is_empty() {
+        return None;
+    }
+    let inner_where = inner_where.as_deref().cloned().into_iter().chain(pushed);
+    Some(SqlAst::Select {
+        columns: columns.clone(),
+        from: Box::new(SqlAst::Subquery(
+            Box::new(SqlAst::Select {
+                columns: inner_columns.clone(),
+                from: inner_from.clone(),
+                where_clause: conjunction(inner_where.collect()),
+                group_by: inner_group_by.clone(),
+                having: inner_having.clone(),
+                order_by: inner_order_by.clone(),
+            }),
+            alias.clone(),
+        )),
+        where_clause: conjunction(kept),
+        group_by: group_by.clone(),
+        having: having.clone(),
+        order_by: order_by.clone(),
+    })
+}
+
+/// Predicate over the columns of the subquery `alias` rewritten over the
+/// expressions computing them, if it can be evaluated before the subquery
+/// aggregates.
+fn push_down(
+    predicate: &SqlAst,
+    alias: &str,
+    columns: &[SqlAst],
+    group_by: Option<&[SqlAst]>,
+) -> Option<SqlAst> {
+    // Window functions see every row of the subquery, which the predicate
+    // would remove.
+    let mut computed = vec![];
+    for column in columns.iter() {
+        let SqlAst::ScalarAlias { expression, alias } = column else {
+            return None;
+        };
+        computed.push((alias, expression));
+    }
+    let known_early = |expression: &Expression| match group_by {
+        Some(group_by) => {
+            group_by.contains(&SqlAst::Scalar(expression.clone()))
+        }
+        None => computed.iter().all(|(_, e)| !e.is_aggregate()),
+    };
+    let expressions = predicate.expressions();
+    if !is_predicate(predicate)
+        || expressions.is_empty()
+        || expressions.iter().any(|e| {
+            !e.identifiers().is_empty() || e.tables() != [Arc::from(alias)]
+        })
+    {
+        return None;
+    }
+    let unknown = Cell::new(false);
+    let rewritten = predicate.map_expressions(&|e| {
+        e.transform(&|e| match e {
+            Expression::Column { table, column } if table.as_ref() == alias => {
+                match computed.iter().find(|(id, _)| *id == column) {
+                    Some((_, expression)) if known_early(expression) => {
+                        Some((*expression).clone())
+                    }
+                    _ => {
+                        unknown.set(true);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        })
+    });
+    (!unknown.get()).then_some(rewritten)
+}
+
+/// Comparisons of expressions and literals, combined with AND and OR.
+fn is_predicate(ast: &SqlAst) -> bool {
+    match ast {
+        SqlAst::Comparison { left, right, .. } => {
+            is_predicate(left) && is_predicate(right)
+        }
+        SqlAst::IsNull { value, .. } => is_predicate(value),
+        SqlAst::Logical { items, .. } => items.iter().all(is_predicate),
+        SqlAst::Scalar(_) | SqlAst::Literal(_) | SqlAst::Boolean(_) => true,
+        _ => false,
+    }
+}
+
+fn conjunction(items: Vec<SqlAst>) -> Option<Box<SqlAst>> {
+    match items.len() {
+        0 => None,
+        1 => items.into_iter().next().map(Box::new),
+        _ => Some(Box::new(SqlAst::Logical {
+            items,
+            variant: LogicalVariant::And,
+        })),
+    }
+}
+
+/// Replaces a query selecting every column of its subquery unchanged, in
+/// order and under the same name, with the subquery.
+pub fn remove_redundant_subqueries(ast: &SqlAst) -> Option<SqlAst> {
+    let SqlAst::Select {
+        columns,
+        from,
+        where_clause: None,
+        group_by: None,
+        having: None,
+        order_by: None,
+    } = ast
+    else {
+        return None;
+    };
+    let SqlAst::Subquery(subquery, alias) = from.as_ref() else {
+        return None;
+    };
+    let SqlAst::Select {
+        columns: inner_columns,
+        ..
+    } = subquery.as_ref()
+    else {
+        return None;
+    };
+    let selects_column = |column: &SqlAst, inner: &SqlAst| {
+        let name = match inner {
+            SqlAst::ScalarAlias { alias, .. }
+            | SqlAst::Alias { alias, .. }
+            | SqlAst::ColumnAlias { alias, .. } => alias,
+            _ => return false,
+        };
+        match column {
+            SqlAst::ScalarAlias {
+                expression: Expression::Column { table, column },
+                alias: output,
+            } => table == alias && column == name && output == name,
+            _ => false,
+        }
+    };
+    let redundant = columns.len() == inner_columns.len()
+        && columns
+            .iter()
+            .zip(inner_columns.iter())
+            .all(|(column, inner)| selects_column(column, inner));
+    redundant.then(|| subquery.as_ref().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arc;
+    use crate::executor::query::SQLGenerator;
+
+    fn scalar(expression: &str) -> SqlAst {
+        SqlAst::Scalar(
+            Expression::parse(expression).expect("Expression should parse"),
+        )
+    }
+
+    fn column(expression: &str, alias: &str) -> SqlAst {
+        SqlAst::ScalarAlias {
+            expression: Expression::parse(expression)
+                .expect("Expression should parse"),
+            alias: Arc::from(alias),
+        }
+    }
+
+    fn compare(expression: &str, operator: Operator, value: i64) -> SqlAst {
+        SqlAst::Comparison {
+            left: Box::new(scalar(expression)),
+            operator,
+            right: Box::new(SqlAst::Literal(Literal::Integer(value))),
+        }
+    }
+
+    fn logical(variant: LogicalVariant, items: Vec<SqlAst>) -> SqlAst {
+        SqlAst::Logical { items, variant }
+    }
+
+    fn select(
+        columns: Vec<SqlAst>,
+        from: SqlAst,
+        where_clause: Option<SqlAst>,
+    ) -> SqlAst {
+        SqlAst::Select {
+            columns,
+            from: Box::new(from),
+            where_clause: where_clause.map(Box::new),
+            group_by: None,
+            having: None,
+            order_by: None,
+        }
+    }
+
+    /// Aggregation of `facts` by campaign, grouped by `group_by`.
+    fn facts(group_by: Vec<SqlAst>) -> SqlAst {
+        SqlAst::Subquery(
+            Box::new(SqlAst::Select {
+                columns: vec![
+                    column("fact_table.campaign_id", "campaign_id"),
+                    column("sum(fact_table.impressions)", "sum_impressions"),
+                ],
+                from: Box::new(SqlAst::Table(
+                    arc!["fact_table"],
+                    arc!["fact_table"],
+                )),
+                where_clause: Some(Box::new(compare(
+                    "fact_table.ts",
+                    Operator::Greater,
+                    0,
+                ))),
+                group_by: Some(group_by),
+                having: None,
+                order_by: None,
+            }),
+            arc!["facts"],
+        )
+    }
+
+    fn optimized(passes: Vec<Pass>, ast: SqlAst) -> String {
+        let ast = Optimizer::new(passes).optimize(ast);
+        SQLGenerator::new().generate_sql(&ast).trim().to_string()
+    }
+
+    #[test]
+    fn test_flatten_logical() {
+        let predicate = logical(
+            LogicalVariant::And,
+            vec![
+                compare("a", Operator::Equal, 1),
+                logical(
+                    LogicalVariant::And,
+                    vec![
+                        compare("b", Operator::Equal, 2),
+                        logical(
+                            LogicalVariant::Or,
+                            vec![logical(
+                                LogicalVariant::Or,
+                                vec![
+                                    compare("c", Operator::Equal, 3),
+                                    compare("d", Operator::Equal, 4),
+                                ],
+                            )],
+                        ),
+                    ],
+                ),
+            ],
+        );
+        let ast = Optimizer::new(vec![flatten_logical]).optimize(predicate);
+
+        assert_eq!(
+            ast,
+            logical(
+                LogicalVariant::And,
+                vec![
+                    compare("a", Operator::Equal, 1),
+                    compare("b", Operator::Equal, 2),
+                    logical(
+                        LogicalVariant::Or,
+                        vec![
+                            compare("c", Operator::Equal, 3),
+                            compare("d", Operator::Equal, 4),
+                        ],
+                    ),
+                ],
+            )
+        );
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let literal =
+            |value| Box::new(SqlAst::Literal(Literal::Integer(value)));
+        let always = SqlAst::Comparison {
+            left: literal(1),
+            operator: Operator::LessOrEqual,
+            right: literal(2),
+        };
+        let never = SqlAst::Comparison {
+            left: SqlAst::Literal(Literal::Date(arc!["2020-02-01"])).into(),
+            operator: Operator::Less,
+            right: SqlAst::Literal(Literal::Date(arc!["2020-01-01"])).into(),
+        };
+        let table = SqlAst::Table(arc!["t"], arc!["t"]);
+
+        let ast = select(
+            vec![column("2 * 3 + a", "b"), column("1 - 2", "c")],
+            table.clone(),
+            Some(logical(
+                LogicalVariant::And,
+                vec![always.clone(), compare("a", Operator::Greater, 2)],
+            )),
+        );
+        assert_eq!(
+            optimized(vec![fold_constants], ast),
+            "SELECT 6 + a AS b, 1 - 2 AS c FROM t t WHERE a > 2"
+        );
+
+        let ast = select(
+            vec![column("a", "a")],
+            table.clone(),
+            Some(logical(
+                LogicalVariant::Or,
+                vec![compare("a", Operator::Greater, 2), always],
+            )),
+        );
+        assert_eq!(
+            optimized(vec![fold_constants], ast),
+            "SELECT a AS a FROM t t"
+        );
+
+        let ast = select(
+            vec![column("a", "a")],
+            table,
+            Some(logical(
+                LogicalVariant::And,
+                vec![compare("a", Operator::Greater, 2), never],
+            )),
+        );
+        assert_eq!(
+            optimized(vec![fold_constants], ast),
+            "SELECT a AS a FROM t t WHERE FALSE"
+        );
+    }
+
+    #[test]
+    fn test_push_down_predicates() {
+        let ast = select(
+            vec![column("facts.sum_impressions", "sum_impressions")],
+            facts(vec![scalar("fact_table.campaign_id")]),
+            Some(logical(
+                LogicalVariant::And,
+                vec![
+                    compare("facts.campaign_id", Operator::Equal, 7),
+                    compare("facts.sum_impressions", Operator::Greater, 10),
+                ],
+            )),
+        );
+        assert_eq!(
+            optimized(vec![flatten_logical, push_down_predicates], ast),
+            "SELECT facts.sum_impressions AS sum_impressions FROM (SELECT fact_table.campaign_id AS campaign_id, sum(fact_table.impressions) AS sum_impressions FROM fact_table fact_table WHERE fact_table.ts > 0 AND fact_table.campaign_id = 7 GROUP BY fact_table.campaign_id) facts WHERE facts.sum_impressions > 10"
+        );
+
+        // Rows filtered before a rollup would change its totals.
+        let ast = select(
+            vec![column("facts.sum_impressions", "sum_impressions")],
+            facts(vec![SqlAst::Rollup(vec![scalar("fact_table.campaign_id")])]),
+            Some(compare("facts.campaign_id", Operator::Equal, 7)),
+        );
+        assert_eq!(
+            Optimizer::new(vec![push_down_predicates]).optimize(ast.clone()),
+            ast
+        );
+    }
+
+    #[test]
+    fn test_remove_redundant_subqueries() {
+        let ast = select(
+            vec![
+                column("facts.campaign_id", "campaign_id"),
+                column("facts.sum_impressions", "sum_impressions"),
+            ],
+            facts(vec![scalar("fact_table.campaign_id")]),
+            None,
+        );
+        assert_eq!(
+            optimized(vec![remove_redundant_subqueries], ast),
+            "SELECT fact_table.campaign_id AS campaign_id, sum(fact_table.impressions) AS sum_impressions FROM fact_table fact_table WHERE fact_table.ts > 0 GROUP BY fact_table.campaign_id"
+        );
+
+        let ast = select(
+            vec![column("facts.campaign_id", "campaign_id")],
+            facts(vec![scalar("fact_table.campaign_id")]),
+            None,
+        );
+        assert_eq!(
+            Optimizer::new(vec![remove_redundant_subqueries])
+                .optimize(ast.clone()),
+            ast
+        );
+    }
+}
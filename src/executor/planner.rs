@@ -1,219 +1,4436 @@
-use crate::domain::models::{Column, Datasource, Filter, ReportRequest};
-use crate::executor::query::{JoinType, LogicalVariant, Operator, SqlAst};
-use crate::rc;
-use std::rc::Rc;
+use crate::domain::calendar::{Clock, Date, SystemClock};
+use crate::domain::expression::{binary, BinaryOperator, Expression};
+use crate::domain::models::{
+    is_timezone, Column, ColumnType, Comparison, Cursor, DataType, Datasource,
+    Filter, Granularity, Literal, Nulls, Order, Pivot, Principal, Report,
+    ReportRequest, Schema, Table, TopN, Totals, ValidationError,
+};
+use crate::domain::registry::{DatasourceRegistry, RegistryError};
+use crate::executor::explain::{
+    joins, tables, Explain, FilterSplit, ResolvedColumn,
+};
+use crate::executor::optimizer::Optimizer;
+use crate::executor::query::{
+    Frame, FrameBound, FrameUnits, JoinType, LogicalVariant, NullsOrder,
+    Operator, SQLGenerator, SortDirection, SqlAst, WindowFunction,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub enum Error {
+    InvalidDatasource(Vec<ValidationError>),
     ColumnNotFound(String),
     MissingFilter(String),
+    InvalidSchema(String),
+    InvalidFilter(String),
+    FormulaCycle(Vec<String>),
+    IncompatibleGrouping(String, String),
+    SortColumnNotRequested(String),
+    InvalidPagination(String),
+    InvalidCursor(String),
+    /// Filter value that does not parse as the type of its column.
+    InvalidValue(String, String),
+    /// Date filter value that does not start a bucket of the requested
+    /// granularity.
+    UnalignedDate(String, String),
+    InvalidTimezone(String),
+    InvalidComparison(String),
+    InvalidTotals(String),
+    InvalidTopN(String),
+    InvalidRunningSum(String),
+    InvalidPivot(String),
+    InvalidDrillAcross(String),
+    /// Datasource named by the request that cannot be planned over.
+    Datasource(RegistryError),
+    /// Request naming another datasource than the planner's: the requested
+    /// name, then the planner's.
+    DatasourceMismatch(String, String),
+    /// Request the security policies of the datasource grant no rows to.
+    Unauthorized(String),
+}
+
+const DATE_COLUMN: &str = "date";
+const FACTS_ALIAS: &str = "facts";
+const CURRENT_ALIAS: &str = "current_period";
+const PREVIOUS_ALIAS: &str = "previous_period";
+const PREVIOUS_SUFFIX: &str = "_previous";
+/// Output column flagging rows as `detail`, `subtotal` or `total` rows.
+const ROW_TYPE_COLUMN: &str = "row_type";
+/// Position of a row within its top N group.
+const RANK_COLUMN: &str = "row_rank";
+const RANKED_ALIAS: &str = "ranked";
+
+/// Formulas expanded into expressions over the aggregated facts, in
+/// dependency order, together with the columns the aggregation has to
+/// compute for them.
+#[derive(Default)]
+struct Formulas {
+    expanded: Vec<(Arc<str>, Expression)>,
+    inputs: Vec<Column>,
+}
+
+impl Formulas {
+    /// Expression of a column in the query over the aggregated facts.
+    fn outer_expression(&self, column_id: &str) -> Expression {
+        match self
+            .expanded
+            .iter()
+            .find(|(id, _)| id.as_ref() == column_id)
+        {
+            Some((_, expression)) => expression.clone(),
+            None => Expression::Column {
+                table: FACTS_ALIAS.into(),
+                column: column_id.into(),
+            },
+        }
+    }
+}
+
+/// Top level filters split by the query stage they apply to.
+#[derive(Default)]
+struct FilterStages<'a> {
+    /// Filters on grouping columns only, applied before aggregation.
+    rows: Vec<&'a Filter>,
+    /// Filters on aggregates, applied in HAVING.
+    aggregates: Vec<&'a Filter>,
+    /// Filters on formulas, applied over the aggregated facts.
+    formulas: Vec<&'a Filter>,
+}
+
+/// Renders a filter comparison at a query stage: the expression of the
+/// column and the value it is compared to.
+type Resolve<'a> = dyn Fn(&Column, Literal) -> (Expression, Literal) + 'a;
+
+/// Aggregation of a period, given its row filters and HAVING filters.
+type PeriodAggregation<'a> =
+    dyn Fn(&[&Filter], &[&Filter]) -> Result<SqlAst, Error> + 'a;
+
+/// Aggregation of a fact table computing the given columns.
+type FactAggregation<'a> =
+    dyn Fn(&Table, Vec<SqlAst>) -> Result<SqlAst, Error> + 'a;
+
+/// Pivot of a request, with its column and the values it spreads the
+/// aggregates over.
+type Pivoted<'a> = (&'a Pivot, Column, Vec<Expression>);
+
+/// Date column of a bucketed report.
+struct Time {
+    /// Fact timestamp as an instant, compared against the date filters.
+    timestamp: Expression,
+    /// Bucket of the fact timestamp the date column is grouped by.
+    bucket: Expression,
+    timezone: Option<Arc<str>>,
+}
+
+impl Time {
+    /// Date filter values are midnights in the report timezone.
+    fn bound(&self, value: Literal) -> Literal {
+        match (&self.timezone, value) {
+            (Some(timezone), Literal::Date(date)) => Literal::Timestamp {
+                date,
+                timezone: timezone.clone(),
+            },
+            (_, value) => value,
+        }
+    }
+}
+
+struct SortKey {
+    column_id: Arc<str>,
+    data_type: DataType,
+    /// Bucket of the date key, whose values are the starts of the buckets.
+    granularity: Option<Granularity>,
+    direction: SortDirection,
+    nulls: Option<NullsOrder>,
+}
+
+impl SortKey {
+    /// Parses a cursor value of the key. Hourly buckets start within a day.
+    fn parse(&self, value: &str) -> Option<Literal> {
+        match self.granularity {
+            Some(Granularity::Hour) => hour_literal(value),
+            _ => self.data_type.parse_literal(value),
+        }
+    }
+
+    /// Whether NULL keys sort after every value. The keys of paginated
+    /// reports always order NULLs explicitly, as the ORDER BY does.
+    fn nulls_last(&self) -> bool {
+        self.nulls == Some(NullsOrder::Last)
+    }
 }
 
 pub struct QueryPlanner {
     datasource: Datasource,
+    schema: Schema,
+    /// Parsed expression of every datasource column, by column id.
+    expressions: HashMap<Arc<str>, Expression>,
+    /// Date relative date ranges are resolved against.
+    clock: Box<dyn Clock>,
+    /// Caller the security policies of the datasource restrict rows for.
+    principal: Option<Principal>,
 }
 
 impl QueryPlanner {
-    pub fn new(datasource: Datasource) -> Self {
-        QueryPlanner { datasource }
+    pub fn new(datasource: Datasource, schema: Schema) -> Result<Self, Error> {
+        datasource.validate().map_err(Error::InvalidDatasource)?;
+        let mut expressions = HashMap::new();
+        for column in datasource.columns.iter() {
+            let expression = column.parse_expression().map_err(|e| {
+                Error::InvalidDatasource(vec![
+                    ValidationError::InvalidExpression(
+                        column.column_id.to_string(),
+                        e,
+                    ),
+                ])
+            })?;
+            expressions.insert(column.column_id.clone(), expression);
+        }
+        Ok(QueryPlanner {
+            datasource,
+            schema,
+            expressions,
+            clock: Box::new(SystemClock),
+            principal: None,
+        })
+    }
+
+    /// Planner over the datasource of the registry the request names.
+    pub fn for_request(
+        registry: &DatasourceRegistry,
+        schema: Schema,
+        request: &ReportRequest,
+    ) -> Result<Self, Error> {
+        let datasource = registry
+            .resolve(request.datasource.as_deref())
+            .map_err(Error::Datasource)?;
+        QueryPlanner::new(datasource.clone(), schema)
+    }
+
+    pub fn with_clock(self, clock: Box<dyn Clock>) -> Self {
+        QueryPlanner { clock, ..self }
+    }
+
+    pub fn with_principal(self, principal: Principal) -> Self {
+        QueryPlanner {
+            principal: Some(principal),
+            ..self
+        }
     }
+
+    /// Plans the request and rewrites the plan with the default optimizer
+    /// passes.
     pub fn plan(&self, request: ReportRequest) -> Result<SqlAst, Error> {
-        let (start_date, end_date) = match request.filters {
-            Filter::And { value } => {
-                let mut start_date = None;
-                let mut end_date = None;
-                for f in value.iter() {
-                    match f {
-                        Filter::Gte { column, value } if column == "date" => {
-                            start_date = Some(value.clone())
-                        }
-                        Filter::Lt { column, value } if column == "date" => {
-                            end_date = Some(value.clone())
-                        }
-                        _ => {}
-                    }
-                }
-                (
-                    start_date.ok_or(Error::MissingFilter(
-                        "start_date".to_string(),
-                    ))?,
-                    end_date
-                        .ok_or(Error::MissingFilter("end_date".to_string()))?,
-                )
+        self.optimized_plan(request).map(|(ast, _)| ast)
+    }
+
+    /// Plans a report from the filters resolved when it was created, so
+    /// that reruns cover the same dates.
+    pub fn plan_report(&self, report: &Report) -> Result<SqlAst, Error> {
+        let request = ReportRequest {
+            filters: report
+                .resolved_filters
+                .clone()
+                .unwrap_or_else(|| report.request.filters.clone()),
+            ..report.request.clone()
+        };
+        self.plan(request)
+    }
+
+    /// Optimized plan of the request, with the filters each clause of it
+    /// applies.
+    fn optimized_plan(
+        &self,
+        request: ReportRequest,
+    ) -> Result<(SqlAst, FilterSplit), Error> {
+        if let Some(name) = &request.datasource {
+            if name.as_str() != self.datasource.name.as_ref() {
+                return Err(Error::DatasourceMismatch(
+                    name.clone(),
+                    self.datasource.name.to_string(),
+                ));
             }
-            _ => {
-                return Err(Error::MissingFilter(
-                    "Expected And filter".to_string(),
-                ))
+        }
+        self.naive_plan(request)
+            .map(|(ast, filters)| (Optimizer::default().optimize(ast), filters))
+    }
+
+    fn naive_plan(
+        &self,
+        request: ReportRequest,
+    ) -> Result<(SqlAst, FilterSplit), Error> {
+        let timezone: Option<Arc<str>> = match &request.timezone {
+            Some(timezone) if !is_timezone(timezone) => {
+                return Err(Error::InvalidTimezone(timezone.clone()))
             }
+            Some(timezone) => Some(timezone.as_str().into()),
+            None => self.datasource.timezone.clone(),
+        };
+        let filters = self
+            .datasource
+            .resolve_filters(&request, self.clock.as_ref())
+            .ok_or_else(|| {
+                Error::InvalidTimezone(
+                    timezone.as_deref().unwrap_or("UTC").to_string(),
+                )
+            })?;
+        let request = ReportRequest {
+            filters: self.restrict(filters)?,
+            ..request
         };
+        require_date_range(&request.filters)?;
         let columns: Vec<Column> = request
             .columns
             .iter()
             .map(|c| self.get_column(c))
             .collect::<Result<Vec<Column>, Error>>()?;
 
-        let aggregation_query = SqlAst::Select {
-            columns: columns
+        let fact = self.schema.fact_table().ok_or(Error::InvalidSchema(
+            "Schema has no fact table".to_string(),
+        ))?;
+        // Days are bucketed in the report timezone, if there is one.
+        let granularity = request
+            .granularity
+            .or(timezone.as_ref().map(|_| Granularity::Day));
+        let time = match granularity {
+            Some(granularity) => {
+                require_aligned_dates(&request.filters, granularity)?;
+                Some(time_expressions(fact, granularity, timezone.clone())?)
+            }
+            None => None,
+        };
+        let request = match granularity {
+            Some(granularity) => ReportRequest {
+                filters: bucket_bounds(&request.filters, granularity)?,
+                ..request
+            },
+            None => request,
+        };
+
+        let stages = self.split_filters(&request.filters)?;
+        let formula_filter_columns = stages
+            .formulas
+            .iter()
+            .flat_map(|f| f.columns())
+            .map(|c| self.get_column(c))
+            .collect::<Result<Vec<Column>, Error>>()?;
+
+        let mut formulas = Formulas::default();
+        for column in columns.iter().chain(formula_filter_columns.iter()) {
+            // Window columns expand to their measure, like a formula.
+            if column.column_type.is_derived() {
+                self.expand_formula(column, &mut vec![], &mut formulas)?;
+            }
+        }
+        let mut aggregated: Vec<Column> = columns
+            .iter()
+            .filter(|c| !c.column_type.is_derived())
+            .cloned()
+            .collect();
+        for input in formulas.inputs.iter().chain(
+            formula_filter_columns
                 .iter()
-                .map(|c| SqlAst::ColumnAlias {
-                    column: c.expression.clone(),
-                    alias: c.column_id.clone(),
-                })
-                .collect::<Vec<SqlAst>>(),
-            from: Box::new(SqlAst::Join {
-                left: Box::new(SqlAst::Table(
-                    rc!["fact_table"],
-                    rc!["fact_table"],
-                )),
-                right: Box::new(SqlAst::Table(
-                    rc!["campaign_hierarchy"],
-                    rc!["campaign_hierarchy"],
-                )),
-                join_type: JoinType::Left,
-                on: Box::new(SqlAst::Comparison {
-                    left: Box::new(SqlAst::Column(rc![
-                        "fact_table.line_item_id"
-                    ])),
-                    operator: Operator::Equal,
-                    right: Box::new(SqlAst::Column(rc![
-                        "campaign_hierarchy.line_item_id"
-                    ])),
-                }),
-            }),
-            where_clause: Some(Box::new(SqlAst::Logical {
-                items: vec![
-                    SqlAst::Comparison {
-                        left: Box::new(SqlAst::Column(rc![
-                            "from_unixtime(fact_table.ts, 'YYYY-mm-dd')"
-                        ])),
-                        operator: Operator::GreaterOrEqual,
-                        right: Box::new(SqlAst::Literal(start_date)),
-                    },
-                    SqlAst::Comparison {
-                        left: Box::new(SqlAst::Column(rc![
-                            "from_unixtime(fact_table.ts, 'YYYY-mm-dd')"
-                        ])),
-                        operator: Operator::Less,
-                        right: Box::new(SqlAst::Literal(end_date)),
+                .filter(|c| !c.column_type.is_derived()),
+        ) {
+            if !aggregated.contains(input) {
+                aggregated.push(input.clone());
+            }
+        }
+        let pivot = self.pivot(
+            &request,
+            &columns,
+            &formulas,
+            granularity,
+            &mut aggregated,
+        )?;
+        let groupings: Vec<&Column> = aggregated
+            .iter()
+            .filter(|c| c.column_type == ColumnType::Grouping)
+            .collect();
+        check_groupings(&aggregated, &groupings)?;
+        for filter in stages.aggregates.iter().chain(stages.formulas.iter()) {
+            for column in filter.columns() {
+                let column = self.get_column(column)?;
+                if column.column_type == ColumnType::Grouping
+                    && !groupings.contains(&&column)
+                {
+                    return Err(Error::InvalidFilter(format!(
+                        "Filter combines aggregates with {}, which is not grouped",
+                        column.column_id
+                    )));
+                }
+            }
+        }
+        let fact_aggregates = self.fact_aggregates(fact, &aggregated)?;
+        let drill_across = fact_aggregates.len() > 1;
+        // Requests of a single fact are answered from the smallest aggregate
+        // table having every column they need, if any. Aggregate tables
+        // store UTC dates.
+        let mut needed: Vec<Column> = aggregated.clone();
+        for filter in stages.rows.iter().chain(stages.aggregates.iter()) {
+            for column in filter.columns() {
+                let column = self.get_column(column)?;
+                if !needed.contains(&column) {
+                    needed.push(column);
+                }
+            }
+        }
+        if let Some((_, column, _)) = &pivot {
+            needed.push(column.clone());
+        }
+        // A compared period is read from the same table as the report, so
+        // its shifted dates have to select whole stored buckets too.
+        let previous_filters = request
+            .compare
+            .map(|comparison| compared_filters(&request.filters, comparison))
+            .transpose()?;
+        let aggregate_table = match drill_across || timezone.is_some() {
+            true => None,
+            false => self.aggregate_table(
+                fact_aggregates[0].0,
+                &needed,
+                std::iter::once(&request.filters)
+                    .chain(previous_filters.as_ref())
+                    .collect::<Vec<&Filter>>()
+                    .as_slice(),
+                columns.iter().any(|c| c.column_id.as_ref() == DATE_COLUMN),
+                granularity,
+            ),
+        };
+        // With a granularity, the date column is the bucket of the fact
+        // timestamp, while row filters compare the timestamp itself.
+        let expression = |c: &Column| match (&aggregate_table, &time) {
+            (Some((table, stored)), _) => {
+                self.aggregated_expression(table, *stored, c, granularity)
+            }
+            (None, Some(time)) if c.column_id.as_ref() == DATE_COLUMN => {
+                time.bucket.clone()
+            }
+            _ => self.expression(c),
+        };
+        let row_filter =
+            |c: &Column, value: Literal| match (&aggregate_table, &time) {
+                (Some((table, _)), _) => (
+                    Expression::Column {
+                        table: table.name.clone(),
+                        column: c.column_id.clone(),
                     },
-                ],
-                variant: LogicalVariant::And,
-            })),
-            group_by: Some(vec![
-                SqlAst::Column(rc![
-                    "from_unixtime(fact_table.ts, 'YYYY-mm-dd')"
-                ]),
-                SqlAst::Column(rc!["fact_table.line_item_id"]),
-                SqlAst::Column(rc!["campaign_hierarchy.campaign_id"]),
-            ]),
-            order_by: None,
+                    value,
+                ),
+                (None, Some(time)) if c.column_id.as_ref() == DATE_COLUMN => {
+                    (time.timestamp.clone(), time.bound(value))
+                }
+                _ => (self.expression(c), value),
+            };
+        let aggregate_filter =
+            |c: &Column, value: Literal| (expression(c), value);
+        let outer_expression =
+            |c: &Column| formulas.outer_expression(&c.column_id);
+        let outer_filter =
+            |c: &Column, value: Literal| (outer_expression(c), value);
+
+        let group_by = group_by(&request, &columns, &groupings, &expression)?;
+        let mut aggregation_columns =
+            pivoted_columns(&aggregated, pivot.as_ref(), &expression);
+        if request.totals.is_some() {
+            aggregation_columns.push(SqlAst::ScalarAlias {
+                expression: row_type(
+                    groupings.iter().map(|c| expression(c)).collect(),
+                ),
+                alias: ROW_TYPE_COLUMN.into(),
+            });
+        }
+        // Grouping expressions and row filters are written against the
+        // first fact table and retargeted to the fact being aggregated.
+        let aggregation = |rows: &[&Filter],
+                           target: &Table,
+                           columns: Vec<SqlAst>,
+                           having: &[&Filter]|
+         -> Result<SqlAst, Error> {
+            let table = match &aggregate_table {
+                Some((table, _)) => table,
+                None => target,
+            };
+            let mut query = SqlAst::Select {
+                columns,
+                from: Box::new(SqlAst::Table(
+                    table.name.clone(),
+                    table.name.clone(),
+                )),
+                where_clause: self.compile_filters(rows, &row_filter)?,
+                group_by: group_by.clone(),
+                having: self.compile_filters(having, &aggregate_filter)?,
+                order_by: None,
+            }
+            .map_expressions(&|e| self.retarget(e, target));
+            // Hierarchies are joined once the retargeted expressions tell
+            // which of them the aggregation reads.
+            if aggregate_table.is_none() {
+                let tables = referenced_tables([&query]);
+                if let SqlAst::Select { from, .. } = &mut query {
+                    **from = self.fact_joins(target, tables.as_deref())?;
+                }
+            }
+            Ok(query)
+        };
+        let target = fact_aggregates[0].0;
+        let aggregation_query = match &previous_filters {
+            _ if drill_across => drill_across_facts(
+                &request,
+                &fact_aggregates,
+                &aggregation_columns,
+                &groupings,
+                &|target, columns| {
+                    aggregation(&stages.rows, target, columns, &[])
+                },
+            )?,
+            None => aggregation(
+                &stages.rows,
+                target,
+                aggregation_columns.clone(),
+                &stages.aggregates,
+            )?,
+            Some(previous_filters) => self.compare(
+                previous_filters,
+                &stages,
+                &groupings,
+                &aggregated,
+                &|rows, having| {
+                    aggregation(
+                        rows,
+                        target,
+                        aggregation_columns.clone(),
+                        having,
+                    )
+                },
+            )?,
         };
 
-        let dim_join = SqlAst::Join {
-            left: Box::new(SqlAst::Subquery(
-                Box::new(aggregation_query),
-                rc!["facts"],
-            )),
-            right: Box::new(SqlAst::Table(
-                rc!["dim_campaign"],
-                rc!["dim_campaign"],
-            )),
-            join_type: JoinType::Left,
-            on: Box::new(SqlAst::Comparison {
-                left: Box::new(SqlAst::Column(rc!["facts.campaign_id"])),
-                operator: Operator::Equal,
-                right: Box::new(SqlAst::Column(rc![
-                    "dim_campaign.campaign_id"
-                ])),
-            }),
+        let outputs = self.outputs(
+            &request,
+            &columns,
+            pivot.as_ref(),
+            &formulas,
+            &groupings,
+        )?;
+        let sort_keys = self.sort_keys(&request, &groupings)?;
+        let mut outer_filters = stages
+            .formulas
+            .iter()
+            .map(|f| self.compile_filter(f, &outer_filter))
+            .collect::<Result<Vec<SqlAst>, Error>>()?;
+        if drill_across {
+            for filter in stages.aggregates.iter() {
+                outer_filters.push(self.compile_filter(filter, &outer_filter)?);
+            }
+        }
+        if let Some(cursor) = &request.cursor {
+            outer_filters.push(keyset(cursor, &sort_keys, &outputs)?);
+        }
+        let owned = |filters: &[&Filter]| {
+            filters.iter().map(|f| (*f).clone()).collect()
         };
+        let mut filters = FilterSplit {
+            where_clause: owned(&stages.rows),
+            having: owned(&stages.aggregates),
+            outer_where: owned(&stages.formulas),
+        };
+        if drill_across {
+            filters.outer_where.append(&mut filters.having);
+        }
 
-        let final_query = SqlAst::Select {
-            columns: vec![],
+        let final_columns = outputs
+            .iter()
+            .map(|(id, value)| match value {
+                SqlAst::Scalar(expression) => SqlAst::ScalarAlias {
+                    expression: expression.clone(),
+                    alias: id.clone(),
+                },
+                _ => SqlAst::Alias {
+                    value: Box::new(value.clone()),
+                    alias: id.clone(),
+                },
+            })
+            .collect::<Vec<SqlAst>>();
+        let order_by = match sort_keys.is_empty() {
+            true => None,
+            false => Some(
+                sort_keys
+                    .iter()
+                    .map(|key| SqlAst::OrderBy {
+                        expression: Box::new(SqlAst::Column(
+                            key.column_id.clone(),
+                        )),
+                        direction: key.direction,
+                        nulls: key.nulls,
+                    })
+                    .collect(),
+            ),
+        };
+        let dim_join = self.dimension_joins(
+            SqlAst::Subquery(Box::new(aggregation_query), FACTS_ALIAS.into()),
+            &groupings,
+            referenced_tables(final_columns.iter().chain(&outer_filters))
+                .as_deref(),
+        )?;
+        let query = SqlAst::Select {
+            columns: final_columns,
             from: Box::new(dim_join),
-            where_clause: None,
+            where_clause: conjunction(outer_filters),
             group_by: None,
+            having: None,
             order_by: None,
         };
-        Ok(final_query)
+        let mut final_query = match &request.top_n {
+            None => query,
+            Some(top_n) => {
+                self.top_n(top_n, &request, &sort_keys, &outputs, query)?
+            }
+        };
+        if let SqlAst::Select {
+            order_by: order, ..
+        } = &mut final_query
+        {
+            *order = order_by;
+        }
+        if request.limit.is_none() && request.offset.is_none() {
+            return Ok((final_query, filters));
+        }
+        let query = SqlAst::Limit {
+            query: Box::new(final_query),
+            limit: request.limit,
+            offset: request.offset,
+        };
+        Ok((query, filters))
+    }
+
+    /// Plans the request and describes the plan: the resolved columns, the
+    /// tables and joins read, the split of the filters and the SQL.
+    pub fn explain(&self, request: ReportRequest) -> Result<Explain, Error> {
+        let (ast, filters) = self.optimized_plan(request.clone())?;
+        let columns = request
+            .columns
+            .iter()
+            .map(|c| {
+                let column = self.get_column(c)?;
+                Ok(ResolvedColumn {
+                    column_id: column.column_id.to_string(),
+                    column_type: column.column_type.clone(),
+                    data_type: column.data_type.clone(),
+                    expression: self.expression(&column).to_string(),
+                })
+            })
+            .collect::<Result<Vec<ResolvedColumn>, Error>>()?;
+        Ok(Explain {
+            columns,
+            tables: tables(&ast),
+            joins: joins(&ast),
+            filters,
+            pivot_columns: self.datasource.pivot_columns(&request),
+            sql: SQLGenerator::new().generate_sql(&ast).trim().to_string(),
+        })
     }
 
-    fn get_column(&self, input: &String) -> Result<Column, Error> {
+    fn get_column(&self, input: &str) -> Result<Column, Error> {
         self.datasource
             .columns
             .iter()
-            .find(|c| c.column_id.to_string() == *input)
+            .find(|c| c.column_id.as_ref() == input)
             .cloned()
-            .ok_or(Error::ColumnNotFound(input.clone()))
+            .ok_or(Error::ColumnNotFound(input.to_string()))
     }
-}
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::domain::models::{
-        Column, ColumnType, Datasource, Filter, ReportRequest,
-    };
 
-    #[test]
-    fn test_plan_success() {
-        // Create a dummy Column for testing.
-        let column = Column {
-            name: std::rc::Rc::from("username"),
-            column_id: std::rc::Rc::from("username"),
-            expression: std::rc::Rc::from("username"),
-            column_type: ColumnType::Grouping,
-            data_type: std::rc::Rc::from("text"),
+    fn expression(&self, column: &Column) -> Expression {
+        self.expressions[&column.column_id].clone()
+    }
+
+    /// Expands a formula over the aggregated facts after expanding the
+    /// formulas it refers to. `path` holds the formulas being expanded and
+    /// is used to detect cycles.
+    fn expand_formula(
+        &self,
+        formula: &Column,
+        path: &mut Vec<Arc<str>>,
+        formulas: &mut Formulas,
+    ) -> Result<(), Error> {
+        if formulas
+            .expanded
+            .iter()
+            .any(|(id, _)| *id == formula.column_id)
+        {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|id| *id == formula.column_id)
+        {
+            let mut cycle: Vec<String> =
+                path[start..].iter().map(|id| id.to_string()).collect();
+            cycle.push(formula.column_id.to_string());
+            return Err(Error::FormulaCycle(cycle));
+        }
+        path.push(formula.column_id.clone());
+        for reference in formula.references() {
+            let column = self.get_column(&reference)?;
+            if column.column_type == ColumnType::Formula {
+                self.expand_formula(&column, path, formulas)?;
+            } else if !formulas.inputs.contains(&column) {
+                formulas.inputs.push(column);
+            }
+        }
+        path.pop();
+
+        let expression =
+            self.expression(formula).replace_identifiers(&|reference| {
+                formulas.outer_expression(reference)
+            });
+        formulas
+            .expanded
+            .push((formula.column_id.clone(), expression));
+        Ok(())
+    }
+
+    /// `row_number()` of the output rows within their top N group, in the
+    /// order of the sort keys.
+    fn rank(
+        &self,
+        top_n: &TopN,
+        request: &ReportRequest,
+        sort_keys: &[SortKey],
+        outputs: &[(Arc<str>, SqlAst)],
+    ) -> Result<SqlAst, Error> {
+        if top_n.limit == 0 {
+            return Err(Error::InvalidTopN(
+                "Top N limit must be positive".to_string(),
+            ));
+        }
+        if request.sort.is_empty() {
+            return Err(Error::InvalidTopN(
+                "Top N needs a sort order".to_string(),
+            ));
+        }
+        if request.cursor.is_some() {
+            return Err(Error::InvalidTopN(
+                "Top N cannot be combined with a cursor".to_string(),
+            ));
+        }
+        let output = |column_id: &str| {
+            outputs
+                .iter()
+                .find(|(id, _)| id.as_ref() == column_id)
+                .map(|(_, value)| value.clone())
         };
+        let partition_by = top_n
+            .per
+            .iter()
+            .map(|column_id| {
+                let column = self.get_column(column_id)?;
+                match (column.column_type, output(column_id)) {
+                    (ColumnType::Grouping, Some(expression)) => Ok(expression),
+                    _ => Err(Error::InvalidTopN(format!(
+                        "{} is not a requested grouping column",
+                        column_id
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<SqlAst>, Error>>()?;
+        Ok(SqlAst::Window {
+            function: WindowFunction::RowNumber,
+            partition_by,
+            order_by: sort_keys
+                .iter()
+                .map(|key| {
+                    match output(&key.column_id)
+                        .expect("sort keys are output columns")
+                    {
+                        expression @ SqlAst::Scalar(_) => Ok(SqlAst::OrderBy {
+                            expression: Box::new(expression),
+                            direction: key.direction,
+                            nulls: key.nulls,
+                        }),
+                        _ => Err(Error::InvalidTopN(format!(
+                            "Top N cannot be ranked by {}",
+                            key.column_id
+                        ))),
+                    }
+                })
+                .collect::<Result<Vec<SqlAst>, Error>>()?,
+            frame: None,
+        })
+    }
 
-        let datasource = Datasource {
-            name: std::rc::Rc::from("default"),
-            columns: vec![column],
-            // Add other required fields if necessary.
+    /// Keeps the first rows of every top N group of the query. Window
+    /// functions cannot be filtered on in the query computing them, so the
+    /// query ranks its rows and an enclosing query filters on the rank.
+    fn top_n(
+        &self,
+        top_n: &TopN,
+        request: &ReportRequest,
+        sort_keys: &[SortKey],
+        outputs: &[(Arc<str>, SqlAst)],
+        mut ranked: SqlAst,
+    ) -> Result<SqlAst, Error> {
+        let rank = self.rank(top_n, request, sort_keys, outputs)?;
+        if let SqlAst::Select { columns, .. } = &mut ranked {
+            columns.push(SqlAst::Alias {
+                value: Box::new(rank),
+                alias: RANK_COLUMN.into(),
+            });
+        }
+        Ok(SqlAst::Select {
+            columns: outputs
+                .iter()
+                .map(|(id, _)| SqlAst::ColumnAlias {
+                    column: format!("{}.{}", RANKED_ALIAS, id).into(),
+                    alias: id.clone(),
+                })
+                .collect(),
+            from: Box::new(SqlAst::Subquery(
+                Box::new(ranked),
+                RANKED_ALIAS.into(),
+            )),
+            where_clause: Some(Box::new(SqlAst::Comparison {
+                left: Box::new(SqlAst::Column(
+                    format!("{}.{}", RANKED_ALIAS, RANK_COLUMN).into(),
+                )),
+                operator: Operator::LessOrEqual,
+                right: Box::new(SqlAst::Literal(Literal::Integer(
+                    top_n.limit as i64,
+                ))),
+            })),
+            group_by: None,
+            having: None,
+            order_by: None,
+        })
+    }
+
+    /// Pivot of the request. The pivot column spreads the aggregates
+    /// instead of grouping them, so it is no longer aggregated.
+    fn pivot<'a>(
+        &self,
+        request: &'a ReportRequest,
+        columns: &[Column],
+        formulas: &Formulas,
+        granularity: Option<Granularity>,
+        aggregated: &mut Vec<Column>,
+    ) -> Result<Option<Pivoted<'a>>, Error> {
+        let Some(pivot) = &request.pivot else {
+            return Ok(None);
         };
+        if !formulas.expanded.is_empty() {
+            return Err(Error::InvalidPivot(
+                "Pivot cannot be combined with formulas".to_string(),
+            ));
+        }
+        let (column, values) =
+            self.pivot_values(pivot, request, columns, granularity)?;
+        aggregated.retain(|c| *c != column);
+        Ok(Some((pivot, column, values)))
+    }
 
-        let planner = QueryPlanner { datasource };
+    /// Joins the aggregation of the current period with the aggregation of
+    /// the compared one, given the row filters and the HAVING filters of
+    /// each.
+    fn compare(
+        &self,
+        previous_filters: &Filter,
+        stages: &FilterStages,
+        groupings: &[&Column],
+        aggregated: &[Column],
+        aggregation: &PeriodAggregation,
+    ) -> Result<SqlAst, Error> {
+        if groupings
+            .iter()
+            .any(|g| g.column_id.as_ref() == DATE_COLUMN)
+        {
+            return Err(Error::InvalidComparison(
+                "Compared periods cannot be grouped by date".to_string(),
+            ));
+        }
+        let previous_stages = self.split_filters(previous_filters)?;
+        Ok(compare_periods(
+            aggregation(&stages.rows, &stages.aggregates)?,
+            aggregation(&previous_stages.rows, &stages.aggregates)?,
+            aggregated,
+        ))
+    }
 
-        let request = ReportRequest {
-            columns: vec!["username".to_string()],
-            filters: Filter::And {
-                value: vec![
-                    Filter::Gte {
-                        column: "date".to_string(),
-                        value: "2020-01-01".to_string(),
-                    },
-                    Filter::Lt {
-                        column: "date".to_string(),
-                        value: "2021-01-01".to_string(),
-                    },
-                ],
-            },
-            sort: vec![],
-            // Add other fields if ReportRequest requires them.
+    /// Output columns over the aggregated facts, in request order: pivoted
+    /// aggregates, running sums and shares of total as window functions,
+    /// compared aggregates and the row type of totals.
+    fn outputs(
+        &self,
+        request: &ReportRequest,
+        columns: &[Column],
+        pivot: Option<&Pivoted>,
+        formulas: &Formulas,
+        groupings: &[&Column],
+    ) -> Result<Vec<(Arc<str>, SqlAst)>, Error> {
+        let outer_expression =
+            |c: &Column| formulas.outer_expression(&c.column_id);
+        let mut outputs: Vec<(Arc<str>, SqlAst)> = vec![];
+        for column in columns.iter() {
+            let value = outer_expression(column);
+            if let Some((pivot, pivot_column, _)) = pivot {
+                if column == pivot_column {
+                    continue;
+                }
+                if column.column_type == ColumnType::Aggregate {
+                    for output in pivot.columns(&[column.column_id.as_ref()]) {
+                        let value =
+                            formulas.outer_expression(&output.column_id);
+                        outputs.push((
+                            output.column_id.into(),
+                            SqlAst::Scalar(value),
+                        ));
+                    }
+                    continue;
+                }
+            }
+            match column.column_type {
+                ColumnType::RunningSum => outputs.push((
+                    column.column_id.clone(),
+                    self.running_sum(
+                        column,
+                        value,
+                        request,
+                        groupings,
+                        &outer_expression,
+                    )?,
+                )),
+                ColumnType::ShareOfTotal => outputs
+                    .push((column.column_id.clone(), share_of_total(value))),
+                ColumnType::Grouping => outputs
+                    .push((column.column_id.clone(), SqlAst::Scalar(value))),
+                _ => {
+                    outputs.push((
+                        column.column_id.clone(),
+                        SqlAst::Scalar(value.clone()),
+                    ));
+                    if request.compare.is_some() {
+                        outputs.extend(
+                            comparison_outputs(&column.column_id, value)
+                                .into_iter()
+                                .map(|(id, e)| (id, SqlAst::Scalar(e))),
+                        );
+                    }
+                }
+            }
+        }
+        if request.totals.is_some() {
+            outputs.push((
+                ROW_TYPE_COLUMN.into(),
+                SqlAst::Scalar(formulas.outer_expression(ROW_TYPE_COLUMN)),
+            ));
+        }
+        Ok(outputs)
+    }
+
+    /// Checks a pivot and parses its values as literals of the pivot column.
+    /// Only aggregates can be pivoted, and the pivoted columns are gone from
+    /// the output, so they cannot be sorted by.
+    fn pivot_values(
+        &self,
+        pivot: &Pivot,
+        request: &ReportRequest,
+        columns: &[Column],
+        granularity: Option<Granularity>,
+    ) -> Result<(Column, Vec<Expression>), Error> {
+        let column = columns
+            .iter()
+            .find(|c| c.column_id.as_ref() == pivot.column)
+            .filter(|c| c.column_type == ColumnType::Grouping)
+            .ok_or(Error::InvalidPivot(format!(
+                "{} is not a requested grouping column",
+                pivot.column
+            )))?;
+        if pivot.values.is_empty() {
+            return Err(Error::InvalidPivot(format!(
+                "Pivot on {} has no values",
+                pivot.column
+            )));
+        }
+        if request.compare.is_some() {
+            return Err(Error::InvalidPivot(
+                "Pivot cannot be combined with a comparison".to_string(),
+            ));
+        }
+        if let Some(column) = columns.iter().find(|c| c.column_type.is_window())
+        {
+            return Err(Error::InvalidPivot(format!(
+                "Pivot cannot be combined with {}",
+                column.column_id
+            )));
+        }
+        for order in request.sort.iter() {
+            let sorted = self.get_column(order.column())?;
+            if sorted == *column || sorted.column_type == ColumnType::Aggregate
+            {
+                return Err(Error::InvalidPivot(format!(
+                    "Pivoted column {} cannot be sorted by",
+                    sorted.column_id
+                )));
+            }
+        }
+        // Values differing only in punctuation name the same output column.
+        let outputs = pivot.columns(&[""]);
+        for (index, output) in outputs.iter().enumerate() {
+            if outputs[..index]
+                .iter()
+                .any(|o| o.column_id == output.column_id)
+            {
+                return Err(Error::InvalidPivot(format!(
+                    "Pivot value {} repeats another value",
+                    output.value
+                )));
+            }
+        }
+        let mut values = vec![];
+        for value in pivot.values.iter() {
+            if let Some(granularity) = granularity {
+                if column.column_id.as_ref() == DATE_COLUMN
+                    && !granularity.is_aligned(value)
+                {
+                    return Err(Error::UnalignedDate(
+                        value.to_string(),
+                        granularity.unit().to_string(),
+                    ));
+                }
+            }
+            values.push(literal_expression(literal(column, value)?));
+        }
+        Ok((column.clone(), values))
+    }
+
+    /// Cumulative sum of the measure in the requested sort order, restarted
+    /// for every combination of the requested grouping columns that are not
+    /// sorted by.
+    fn running_sum(
+        &self,
+        column: &Column,
+        measure: Expression,
+        request: &ReportRequest,
+        groupings: &[&Column],
+        outer_expression: &dyn Fn(&Column) -> Expression,
+    ) -> Result<SqlAst, Error> {
+        let mut order_by = vec![];
+        for order in request.sort.iter() {
+            let sorted = self.get_column(order.column())?;
+            if sorted.column_type.is_window() {
+                continue;
+            }
+            order_by.push(SqlAst::OrderBy {
+                expression: Box::new(SqlAst::Scalar(outer_expression(&sorted))),
+                direction: match order {
+                    Order::Asc { .. } => SortDirection::Asc,
+                    Order::Desc { .. } => SortDirection::Desc,
+                },
+                nulls: order.nulls().map(|nulls| match nulls {
+                    Nulls::First => NullsOrder::First,
+                    Nulls::Last => NullsOrder::Last,
+                }),
+            });
+        }
+        if order_by.is_empty() {
+            return Err(Error::InvalidRunningSum(match request.sort.first() {
+                None => format!("{} needs a sort order", column.column_id),
+                Some(order) => format!(
+                    "{} cannot be ordered by window column {}",
+                    column.column_id,
+                    order.column()
+                ),
+            }));
+        }
+        let partition_by = groupings
+            .iter()
+            .filter(|g| {
+                request.columns.iter().any(|c| c == g.column_id.as_ref())
+                    && !request
+                        .sort
+                        .iter()
+                        .any(|o| o.column() == g.column_id.as_ref())
+            })
+            .map(|g| SqlAst::Scalar(outer_expression(g)))
+            .collect();
+        Ok(SqlAst::Window {
+            function: WindowFunction::Sum(Box::new(SqlAst::Scalar(measure))),
+            partition_by,
+            order_by,
+            frame: Some(Frame {
+                units: FrameUnits::Rows,
+                start: FrameBound::UnboundedPreceding,
+                end: FrameBound::CurrentRow,
+            }),
+        })
+    }
+
+    /// Sort keys of the final query: the requested sort over output
+    /// columns, followed by the remaining grouping columns when the report
+    /// is paginated so that every row has a stable position.
+    fn sort_keys(
+        &self,
+        request: &ReportRequest,
+        groupings: &[&Column],
+    ) -> Result<Vec<SortKey>, Error> {
+        if request.offset.is_some() && request.cursor.is_some() {
+            return Err(Error::InvalidPagination(
+                "offset and cursor cannot be combined".to_string(),
+            ));
+        }
+        let bucket = |column: &str| {
+            request.granularity.filter(|_| column == DATE_COLUMN)
         };
+        let mut keys = vec![];
+        for order in request.sort.iter() {
+            if !request.columns.iter().any(|c| c == order.column()) {
+                return Err(Error::SortColumnNotRequested(
+                    order.column().to_string(),
+                ));
+            }
+            keys.push(SortKey {
+                column_id: order.column().into(),
+                data_type: self.get_column(order.column())?.data_type,
+                granularity: bucket(order.column()),
+                direction: match order {
+                    Order::Asc { .. } => SortDirection::Asc,
+                    Order::Desc { .. } => SortDirection::Desc,
+                },
+                nulls: order.nulls().map(|nulls| match nulls {
+                    Nulls::First => NullsOrder::First,
+                    Nulls::Last => NullsOrder::Last,
+                }),
+            });
+        }
+        let paginated = request.limit.is_some()
+            || request.offset.is_some()
+            || request.cursor.is_some();
+        if paginated {
+            for grouping in groupings.iter() {
+                let requested = request
+                    .columns
+                    .iter()
+                    .any(|c| c == grouping.column_id.as_ref());
+                if requested
+                    && !keys.iter().any(|k| k.column_id == grouping.column_id)
+                {
+                    keys.push(SortKey {
+                        column_id: grouping.column_id.clone(),
+                        data_type: grouping.data_type.clone(),
+                        granularity: bucket(&grouping.column_id),
+                        direction: SortDirection::Asc,
+                        nulls: None,
+                    });
+                }
+            }
+            // Pages only line up with the keyset when the position of NULL
+            // keys is not left to the database.
+            for key in keys.iter_mut() {
+                key.nulls.get_or_insert(match key.direction {
+                    SortDirection::Asc => NullsOrder::Last,
+                    SortDirection::Desc => NullsOrder::First,
+                });
+            }
+        }
+        Ok(keys)
+    }
 
-        let result = planner.plan(request);
-        assert!(result.is_ok());
+    /// ANDs the row restrictions of the security policies into the top
+    /// level conjunction of the request filters. Request filters can only
+    /// narrow the rows further.
+    fn restrict(&self, filters: Filter) -> Result<Filter, Error> {
+        let policies = &self.datasource.policies;
+        if policies.is_empty() {
+            return Ok(filters);
+        }
+        let principal = self.principal.as_ref().ok_or(Error::Unauthorized(
+            format!("Datasource {} requires a principal", self.datasource.name),
+        ))?;
+        let Filter::And { mut value } = filters else {
+            return Err(Error::MissingFilter(
+                "Expected And filter".to_string(),
+            ));
+        };
+        for policy in policies.iter() {
+            let mut allowed: Vec<Filter> = principal
+                .values(&policy.attribute)
+                .iter()
+                .map(|v| Filter::Eq {
+                    column: policy.column.to_string(),
+                    value: v.clone(),
+                })
+                .collect();
+            value.push(match allowed.len() {
+                0 => {
+                    return Err(Error::Unauthorized(format!(
+                        "Principal {} has no {} to restrict {} to",
+                        principal.name, policy.attribute, policy.column
+                    )))
+                }
+                1 => allowed.remove(0),
+                _ => Filter::Or { value: allowed },
+            });
+        }
+        Ok(Filter::And { value })
     }
 
-    #[test]
-    fn test_plan_missing_filter() {
-        let datasource = Datasource {
-            name: std::rc::Rc::from("default"),
-            columns: vec![],
-            // Add other required fields if necessary.
+    /// Splits the top level conjunction of the request filters by the
+    /// most aggregated column each filter refers to.
+    fn split_filters<'a>(
+        &self,
+        filters: &'a Filter,
+    ) -> Result<FilterStages<'a>, Error> {
+        let mut stages = FilterStages::default();
+        let Filter::And { value } = filters else {
+            return Err(Error::MissingFilter(
+                "Expected And filter".to_string(),
+            ));
         };
+        for filter in value.iter() {
+            let mut column_types = vec![];
+            for column in filter.columns() {
+                column_types.push(self.get_column(column)?.column_type);
+            }
+            if column_types.iter().any(|t| t.is_window()) {
+                return Err(Error::InvalidFilter(format!(
+                    "Filter on {} refers to a window column",
+                    filter.columns().join(", ")
+                )));
+            }
+            if column_types.contains(&ColumnType::Formula) {
+                stages.formulas.push(filter);
+            } else if column_types.contains(&ColumnType::Aggregate) {
+                stages.aggregates.push(filter);
+            } else {
+                stages.rows.push(filter);
+            }
+        }
+        Ok(stages)
+    }
 
-        let planner = QueryPlanner { datasource };
+    fn compile_filters(
+        &self,
+        filters: &[&Filter],
+        resolve: &Resolve,
+    ) -> Result<Option<Box<SqlAst>>, Error> {
+        Ok(conjunction(
+            filters
+                .iter()
+                .map(|f| self.compile_filter(f, resolve))
+                .collect::<Result<Vec<SqlAst>, Error>>()?,
+        ))
+    }
 
-        let request = ReportRequest {
-            columns: vec![],
-            filters: Filter::Or { value: vec![] },
-            sort: vec![],
-            // Add other fields if ReportRequest requires them.
+    /// Compiles a filter tree into predicates over the column expressions
+    /// given by `resolve`.
+    fn compile_filter(
+        &self,
+        filter: &Filter,
+        resolve: &Resolve,
+    ) -> Result<SqlAst, Error> {
+        let (column, operator, value) = match filter {
+            Filter::And { value } => {
+                return self.compile_logical(
+                    value,
+                    LogicalVariant::And,
+                    resolve,
+                )
+            }
+            Filter::Or { value } => {
+                return self.compile_logical(value, LogicalVariant::Or, resolve)
+            }
+            Filter::Eq { column, value } => (column, Operator::Equal, value),
+            Filter::Lt { column, value } => (column, Operator::Less, value),
+            Filter::Lte { column, value } => {
+                (column, Operator::LessOrEqual, value)
+            }
+            Filter::Gt { column, value } => (column, Operator::Greater, value),
+            Filter::Gte { column, value } => {
+                (column, Operator::GreaterOrEqual, value)
+            }
+            Filter::Relative { column, .. } => {
+                return Err(Error::InvalidFilter(format!(
+                    "Unresolved relative range on {}",
+                    column
+                )))
+            }
         };
+        let column = self.get_column(column)?;
+        let (left, right) = resolve(&column, literal(&column, value)?);
+        Ok(SqlAst::Comparison {
+            left: Box::new(SqlAst::Scalar(left)),
+            operator,
+            right: Box::new(SqlAst::Literal(right)),
+        })
+    }
 
-        let result = planner.plan(request);
-        assert!(result.is_err());
+    fn compile_logical(
+        &self,
+        filters: &[Filter],
+        variant: LogicalVariant,
+        resolve: &Resolve,
+    ) -> Result<SqlAst, Error> {
+        if filters.is_empty() {
+            return Err(Error::InvalidFilter(format!(
+                "Empty {:?} filter",
+                variant
+            )));
+        }
+        Ok(SqlAst::Logical {
+            items: filters
+                .iter()
+                .map(|f| self.compile_filter(f, resolve))
+                .collect::<Result<Vec<SqlAst>, Error>>()?,
+            variant,
+        })
+    }
+
+    /// Joins the hierarchy and dimension tables among `tables` to the fact
+    /// table, or every one of them when the referenced tables are unknown.
+    /// Hierarchies join on their join column. Dimensions join on the
+    /// datasource column named like their join column, after the
+    /// hierarchies its expression reads.
+    fn fact_joins(
+        &self,
+        fact: &Table,
+        tables: Option<&[Arc<str>]>,
+    ) -> Result<SqlAst, Error> {
+        let mut tables: Option<Vec<Arc<str>>> = tables.map(<[_]>::to_vec);
+        let mut dimensions = vec![];
+        for dimension in self.schema.dimensions() {
+            if tables
+                .as_ref()
+                .is_some_and(|t| !t.contains(&dimension.name))
+            {
+                continue;
+            }
+            let column = join_column(dimension)?;
+            let key = match self.get_column(column) {
+                Ok(key) => self.retarget(&self.expression(&key), fact),
+                Err(_) if tables.is_none() => continue,
+                Err(_) => {
+                    return Err(Error::InvalidSchema(format!(
+                        "Dimension {} joins on {}, which is not a datasource column",
+                        dimension.name, column
+                    )))
+                }
+            };
+            if !key.identifiers().is_empty() {
+                tables = None;
+            }
+            if let Some(tables) = tables.as_mut() {
+                for table in key.tables() {
+                    if !tables.contains(&table) {
+                        tables.push(table);
+                    }
+                }
+            }
+            dimensions.push((dimension, column, key));
+        }
+        let joined = self
+            .schema
+            .hierarchies()
+            .filter(|h| tables.as_ref().is_none_or(|t| t.contains(&h.name)))
+            .try_fold(
+                SqlAst::Table(fact.name.clone(), fact.name.clone()),
+                |left, hierarchy| {
+                    let column = join_column(hierarchy)?;
+                    Ok(SqlAst::Join {
+                        left: Box::new(left),
+                        right: Box::new(SqlAst::Table(
+                            hierarchy.name.clone(),
+                            hierarchy.name.clone(),
+                        )),
+                        join_type: JoinType::Left,
+                        on: Box::new(equals(
+                            qualified(&fact.name, column),
+                            qualified(&hierarchy.name, column),
+                        )),
+                    })
+                },
+            )?;
+        Ok(dimensions.into_iter().fold(
+            joined,
+            |left, (dimension, column, key)| SqlAst::Join {
+                left: Box::new(left),
+                right: Box::new(SqlAst::Table(
+                    dimension.name.clone(),
+                    dimension.name.clone(),
+                )),
+                join_type: JoinType::Left,
+                on: Box::new(equals(
+                    SqlAst::Scalar(key),
+                    qualified(&dimension.name, column),
+                )),
+            },
+        ))
+    }
+
+    /// Fact tables computing the aggregated columns, in schema order, with
+    /// the aggregates of each. Aggregates referring to no fact table are
+    /// computed from the first one, which is also used when nothing is
+    /// aggregated.
+    fn fact_aggregates<'a>(
+        &'a self,
+        first: &'a Table,
+        aggregated: &'a [Column],
+    ) -> Result<Vec<(&'a Table, Vec<&'a Column>)>, Error> {
+        let mut facts: Vec<(&Table, Vec<&Column>)> = vec![];
+        for column in aggregated.iter() {
+            if column.column_type == ColumnType::Grouping {
+                continue;
+            }
+            let tables = self.expression(column).tables();
+            let referenced: Vec<&Table> = self
+                .schema
+                .facts()
+                .filter(|f| tables.contains(&f.name))
+                .collect();
+            let fact = match referenced[..] {
+                [] => first,
+                [fact] => fact,
+                _ => {
+                    return Err(Error::InvalidDrillAcross(format!(
+                        "{} refers to several fact tables",
+                        column.column_id
+                    )))
+                }
+            };
+            match facts.iter_mut().find(|(f, _)| f.name == fact.name) {
+                Some((_, aggregates)) => aggregates.push(column),
+                None => facts.push((fact, vec![column])),
+            }
+        }
+        if facts.is_empty() {
+            facts.push((first, vec![]));
+        }
+        facts.sort_by_key(|(f, _)| {
+            self.schema.facts().position(|t| t.name == f.name)
+        });
+        Ok(facts)
+    }
+
+    /// The smallest aggregate table of the fact having every needed column,
+    /// with the date stored at a granularity the date filters select whole
+    /// buckets of and, if the report is `bucketed` by date, the requested
+    /// granularity can be built from.
+    fn aggregate_table(
+        &self,
+        fact: &Table,
+        needed: &[Column],
+        filters: &[&Filter],
+        bucketed: bool,
+        granularity: Option<Granularity>,
+    ) -> Option<(&Table, Option<Granularity>)> {
+        self.schema
+            .aggregates()
+            .filter(|(_, aggregate)| aggregate.fact == fact.name)
+            .filter(|(_, aggregate)| {
+                needed.iter().all(|c| match c.column_type {
+                    ColumnType::Grouping => {
+                        aggregate.groupings.contains(&c.column_id)
+                            && (c.column_id.as_ref() != DATE_COLUMN
+                                || aggregate.granularity.is_some_and(
+                                    |stored| {
+                                        filters.iter().all(|f| {
+                                            selects_stored_buckets(f, stored)
+                                        }) && (!bucketed
+                                            || granularity.is_some_and(|g| {
+                                                g.contains(stored)
+                                            }))
+                                    },
+                                ))
+                    }
+                    ColumnType::Aggregate => {
+                        aggregate.measures.contains(&c.column_id)
+                            && reaggregation(&self.expression(c)).is_some()
+                    }
+                    _ => false,
+                })
+            })
+            .min_by_key(|(_, aggregate)| aggregate.rows)
+            .map(|(table, aggregate)| (table, aggregate.granularity))
+    }
+
+    /// Expression of a column over an aggregate table, which stores
+    /// grouping columns and aggregates under their column ids. Aggregates
+    /// are combined again and the stored date is bucketed by the requested
+    /// granularity.
+    fn aggregated_expression(
+        &self,
+        table: &Table,
+        stored: Option<Granularity>,
+        column: &Column,
+        granularity: Option<Granularity>,
+    ) -> Expression {
+        let value = Expression::Column {
+            table: table.name.clone(),
+            column: column.column_id.clone(),
+        };
+        match (&column.column_type, granularity) {
+            (ColumnType::Aggregate, _) => Expression::Function {
+                name: reaggregation(&self.expression(column))
+                    .unwrap_or("sum")
+                    .into(),
+                args: vec![value],
+            },
+            (_, Some(bucket))
+                if column.column_id.as_ref() == DATE_COLUMN
+                    && Some(bucket) != stored =>
+            {
+                Expression::Function {
+                    name: "date_trunc".into(),
+                    args: vec![Expression::String(bucket.unit().into()), value],
+                }
+            }
+            _ => value,
+        }
+    }
+
+    /// Points columns of any fact table at `target`, mapping timestamp
+    /// columns to the timestamp of the target. Conformed columns have the
+    /// same name in every fact table.
+    fn retarget(&self, expression: &Expression, target: &Table) -> Expression {
+        expression.transform(&|e| {
+            let Expression::Column { table, column } = e else {
+                return None;
+            };
+            let source = self.schema.facts().find(|f| f.name == *table)?;
+            let column = match (&source.timestamp, &target.timestamp) {
+                (Some(from), Some(to)) if from == column => to.clone(),
+                _ => column.clone(),
+            };
+            Some(Expression::Column {
+                table: target.name.clone(),
+                column,
+            })
+        })
+    }
+
+    /// Joins the dimension tables among `tables` whose join column is one
+    /// of the grouping columns exposed by the aggregated facts. Every such
+    /// dimension is joined when the referenced tables are unknown.
+    fn dimension_joins(
+        &self,
+        facts: SqlAst,
+        groupings: &[&Column],
+        tables: Option<&[Arc<str>]>,
+    ) -> Result<SqlAst, Error> {
+        let mut joined = facts;
+        for dimension in self.schema.dimensions() {
+            if tables.is_some_and(|t| !t.contains(&dimension.name)) {
+                continue;
+            }
+            let column = join_column(dimension)?;
+            if !groupings.iter().any(|g| g.column_id.as_ref() == column) {
+                continue;
+            }
+            joined = SqlAst::Join {
+                left: Box::new(joined),
+                right: Box::new(SqlAst::Table(
+                    dimension.name.clone(),
+                    dimension.name.clone(),
+                )),
+                join_type: JoinType::Left,
+                on: Box::new(equals(
+                    qualified(FACTS_ALIAS, column),
+                    qualified(&dimension.name, column),
+                )),
+            };
+        }
+        Ok(joined)
+    }
+}
+
+/// Joins the aggregations of the current and the compared period on the
+/// grouping columns. The compared aggregates are suffixed with
+/// `_previous`, so the result stands in for the aggregated facts.
+fn compare_periods(
+    current: SqlAst,
+    previous: SqlAst,
+    aggregated: &[Column],
+) -> SqlAst {
+    let mut columns = vec![];
+    let mut keys = vec![];
+    for column in aggregated.iter() {
+        let id = &column.column_id;
+        let current_column = Expression::Column {
+            table: CURRENT_ALIAS.into(),
+            column: id.clone(),
+        };
+        let previous_column = Expression::Column {
+            table: PREVIOUS_ALIAS.into(),
+            column: id.clone(),
+        };
+        if column.column_type == ColumnType::Grouping {
+            columns.push(SqlAst::ScalarAlias {
+                expression: Expression::Function {
+                    name: "coalesce".into(),
+                    args: vec![current_column, previous_column],
+                },
+                alias: id.clone(),
+            });
+            keys.push(equals(
+                qualified(CURRENT_ALIAS, id),
+                qualified(PREVIOUS_ALIAS, id),
+            ));
+        } else {
+            columns.push(SqlAst::ScalarAlias {
+                expression: current_column,
+                alias: id.clone(),
+            });
+            columns.push(SqlAst::ScalarAlias {
+                expression: previous_column,
+                alias: format!("{}{}", id, PREVIOUS_SUFFIX).into(),
+            });
+        }
+    }
+    // Without groupings both sides are a single row.
+    let on = match conjunction(keys) {
+        Some(on) => on,
+        None => Box::new(equals(
+            SqlAst::Literal(Literal::Integer(1)),
+            SqlAst::Literal(Literal::Integer(1)),
+        )),
+    };
+    SqlAst::Select {
+        columns,
+        from: Box::new(SqlAst::Join {
+            left: Box::new(SqlAst::Subquery(
+                Box::new(current),
+                CURRENT_ALIAS.into(),
+            )),
+            right: Box::new(SqlAst::Subquery(
+                Box::new(previous),
+                PREVIOUS_ALIAS.into(),
+            )),
+            join_type: JoinType::Full,
+            on,
+        }),
+        where_clause: None,
+        group_by: None,
+        having: None,
+        order_by: None,
+    }
+}
+
+/// Function aggregating the partial results of an aggregate expression,
+/// if it is a single aggregate whose results can be combined.
+fn reaggregation(expression: &Expression) -> Option<&'static str> {
+    let Expression::Function { name, args } = expression else {
+        return None;
+    };
+    if args.iter().any(|a| a.is_aggregate()) {
+        return None;
+    }
+    match name.to_lowercase().as_str() {
+        "sum" | "count" => Some("sum"),
+        "min" => Some("min"),
+        "max" => Some("max"),
+        _ => None,
+    }
+}
+
+/// Full joins the aggregations of several fact tables on the grouping
+/// columns, so that the result stands in for the aggregated facts. Each
+/// aggregation is aliased by its fact table.
+fn join_facts(
+    aggregations: Vec<(Arc<str>, SqlAst, Vec<&Column>)>,
+    groupings: &[&Column],
+) -> SqlAst {
+    let aliases: Vec<Arc<str>> = aggregations
+        .iter()
+        .map(|(alias, ..)| alias.clone())
+        .collect();
+    // A grouping value comes from the first aggregation having the row.
+    let grouping = |aliases: &[Arc<str>], id: &Arc<str>| -> Expression {
+        let mut values: Vec<Expression> = aliases
+            .iter()
+            .map(|alias| Expression::Column {
+                table: alias.clone(),
+                column: id.clone(),
+            })
+            .collect();
+        match values.len() {
+            1 => values.remove(0),
+            _ => Expression::Function {
+                name: "coalesce".into(),
+                args: values,
+            },
+        }
+    };
+    let mut columns: Vec<SqlAst> = groupings
+        .iter()
+        .map(|g| SqlAst::ScalarAlias {
+            expression: grouping(&aliases, &g.column_id),
+            alias: g.column_id.clone(),
+        })
+        .collect();
+    let mut from: Option<SqlAst> = None;
+    for (index, (alias, aggregation, aggregates)) in
+        aggregations.into_iter().enumerate()
+    {
+        for aggregate in aggregates.iter() {
+            columns.push(SqlAst::ScalarAlias {
+                expression: Expression::Column {
+                    table: alias.clone(),
+                    column: aggregate.column_id.clone(),
+                },
+                alias: aggregate.column_id.clone(),
+            });
+        }
+        let subquery = SqlAst::Subquery(Box::new(aggregation), alias.clone());
+        from = Some(match from {
+            None => subquery,
+            Some(left) => {
+                let keys = groupings
+                    .iter()
+                    .map(|g| {
+                        equals(
+                            SqlAst::Scalar(grouping(
+                                &aliases[..index],
+                                &g.column_id,
+                            )),
+                            qualified(&alias, &g.column_id),
+                        )
+                    })
+                    .collect();
+                // Without groupings every side is a single row.
+                let on = conjunction(keys).unwrap_or(Box::new(equals(
+                    SqlAst::Literal(Literal::Integer(1)),
+                    SqlAst::Literal(Literal::Integer(1)),
+                )));
+                SqlAst::Join {
+                    left: Box::new(left),
+                    right: Box::new(subquery),
+                    join_type: JoinType::Full,
+                    on,
+                }
+            }
+        });
+    }
+    SqlAst::Select {
+        columns,
+        from: Box::new(from.expect("at least one fact is aggregated")),
+        where_clause: None,
+        group_by: None,
+        having: None,
+        order_by: None,
+    }
+}
+
+/// Aggregations of every fact table the aggregates are computed from, full
+/// joined on the grouping columns. Aggregate filters apply to the joined
+/// facts, as a row missing from one fact is still a row of the report.
+fn drill_across_facts(
+    request: &ReportRequest,
+    fact_aggregates: &[(&Table, Vec<&Column>)],
+    aggregation_columns: &[SqlAst],
+    groupings: &[&Column],
+    aggregation: &FactAggregation,
+) -> Result<SqlAst, Error> {
+    if request.compare.is_some()
+        || request.totals.is_some()
+        || request.pivot.is_some()
+    {
+        return Err(Error::InvalidDrillAcross(
+            "Measures of several fact tables cannot be compared, totalled or pivoted".to_string(),
+        ));
+    }
+    let mut aggregations = vec![];
+    for (target, aggregates) in fact_aggregates.iter() {
+        let columns = aggregation_columns
+            .iter()
+            .filter(|c| match c {
+                SqlAst::ScalarAlias { alias, .. } => {
+                    groupings.iter().any(|g| g.column_id == *alias)
+                        || aggregates.iter().any(|a| a.column_id == *alias)
+                }
+                _ => false,
+            })
+            .cloned()
+            .collect();
+        aggregations.push((
+            target.name.clone(),
+            aggregation(target, columns)?,
+            aggregates.clone(),
+        ));
+    }
+    Ok(join_facts(aggregations, groupings))
+}
+
+/// GROUP BY of the aggregation: the grouping columns, with the subtotal and
+/// total levels of the requested totals.
+fn group_by(
+    request: &ReportRequest,
+    columns: &[Column],
+    groupings: &[&Column],
+    expression: &dyn Fn(&Column) -> Expression,
+) -> Result<Option<Vec<SqlAst>>, Error> {
+    if request.totals.is_some() {
+        if request.compare.is_some() {
+            return Err(Error::InvalidTotals(
+                "Totals cannot be combined with a comparison".to_string(),
+            ));
+        }
+        // Subtotal and total rows have NULL grouping keys, which a keyset
+        // on the groupings would skip.
+        if request.cursor.is_some() {
+            return Err(Error::InvalidTotals(
+                "Totals cannot be combined with a cursor".to_string(),
+            ));
+        }
+        if groupings.is_empty() {
+            return Err(Error::InvalidTotals(
+                "Totals need grouping columns".to_string(),
+            ));
+        }
+        if let Some(column) = columns.iter().find(|c| c.column_type.is_window())
+        {
+            return Err(Error::InvalidTotals(format!(
+                "Totals cannot be combined with {}",
+                column.column_id
+            )));
+        }
+    }
+    let grouped = |columns: &[&Column]| -> Vec<SqlAst> {
+        columns
+            .iter()
+            .map(|c| SqlAst::Scalar(expression(c)))
+            .collect()
+    };
+    Ok(match &request.totals {
+        _ if groupings.is_empty() => None,
+        None => Some(grouped(groupings)),
+        Some(Totals::Rollup) => Some(vec![SqlAst::Rollup(grouped(groupings))]),
+        Some(Totals::Cube) => Some(vec![SqlAst::Cube(grouped(groupings))]),
+        Some(Totals::Levels(levels)) => {
+            let mut sets = vec![grouped(groupings)];
+            for level in levels.iter() {
+                sets.push(grouped(&total_level(level, groupings)?));
+            }
+            Some(vec![SqlAst::GroupingSets(sets)])
+        }
+    })
+}
+
+/// Columns the aggregation computes, with one conditional aggregate per
+/// pivot value for the pivoted aggregates.
+fn pivoted_columns(
+    aggregated: &[Column],
+    pivot: Option<&Pivoted>,
+    expression: &dyn Fn(&Column) -> Expression,
+) -> Vec<SqlAst> {
+    let mut columns = vec![];
+    for c in aggregated.iter() {
+        match pivot {
+            Some((pivot, column, values))
+                if c.column_type == ColumnType::Aggregate =>
+            {
+                let operand = expression(column);
+                for (output, value) in pivot
+                    .columns(&[c.column_id.as_ref()])
+                    .into_iter()
+                    .zip(values.iter())
+                {
+                    columns.push(SqlAst::ScalarAlias {
+                        expression: expression(c)
+                            .filter_aggregates(&operand, value),
+                        alias: output.column_id.into(),
+                    });
+                }
+            }
+            _ => columns.push(SqlAst::ScalarAlias {
+                expression: expression(c),
+                alias: c.column_id.clone(),
+            }),
+        }
+    }
+    columns
+}
+
+/// Grouping columns of a requested total level, which have to be requested
+/// groupings themselves.
+fn total_level<'a>(
+    level: &[String],
+    groupings: &[&'a Column],
+) -> Result<Vec<&'a Column>, Error> {
+    level
+        .iter()
+        .map(|id| {
+            groupings
+                .iter()
+                .find(|g| g.column_id.as_ref() == id)
+                .copied()
+                .ok_or(Error::InvalidTotals(format!(
+                    "{} is not a requested grouping",
+                    id
+                )))
+        })
+        .collect()
+}
+
+/// `CASE grouping(<groupings>) WHEN 0 THEN 'detail' WHEN <all> THEN 'total'
+/// ELSE 'subtotal' END`, where `grouping` has a bit set for every column
+/// totalled over.
+fn row_type(groupings: Vec<Expression>) -> Expression {
+    let all = (1u64 << groupings.len()) - 1;
+    let text = |value: &str| Expression::String(value.into());
+    Expression::Case {
+        operand: Box::new(Expression::Function {
+            name: "grouping".into(),
+            args: groupings,
+        }),
+        branches: vec![
+            (Expression::Number("0".into()), text("detail")),
+            (Expression::Number(all.to_string().into()), text("total")),
+        ],
+        otherwise: Some(Box::new(text("subtotal"))),
+    }
+}
+
+/// Percentage of the measure over all rows of the report.
+fn share_of_total(measure: Expression) -> SqlAst {
+    SqlAst::Binary {
+        left: Box::new(SqlAst::Scalar(binary(
+            Expression::Number("100.0".into()),
+            BinaryOperator::Multiply,
+            measure.clone(),
+        ))),
+        operator: BinaryOperator::Divide,
+        right: Box::new(SqlAst::Function {
+            name: "nullif".into(),
+            args: vec![
+                SqlAst::Window {
+                    function: WindowFunction::Sum(Box::new(SqlAst::Scalar(
+                        measure,
+                    ))),
+                    partition_by: vec![],
+                    order_by: vec![],
+                    frame: None,
+                },
+                SqlAst::Literal(Literal::Integer(0)),
+            ],
+        }),
+    }
+}
+
+/// Previous value, absolute change and percent change of a compared output
+/// column, given its value over the aggregated facts.
+fn comparison_outputs(
+    column_id: &str,
+    value: Expression,
+) -> Vec<(Arc<str>, Expression)> {
+    let previous = value.transform(&|e| match e {
+        Expression::Column { table, column }
+            if table.as_ref() == FACTS_ALIAS =>
+        {
+            Some(Expression::Column {
+                table: table.clone(),
+                column: format!("{}{}", column, PREVIOUS_SUFFIX).into(),
+            })
+        }
+        _ => None,
+    });
+    let change = binary(value, BinaryOperator::Subtract, previous.clone());
+    let change_pct = binary(
+        binary(
+            Expression::Number("100.0".into()),
+            BinaryOperator::Multiply,
+            change.clone(),
+        ),
+        BinaryOperator::Divide,
+        Expression::Function {
+            name: "nullif".into(),
+            args: vec![previous.clone(), Expression::Number("0".into())],
+        },
+    );
+    vec![
+        (format!("{}{}", column_id, PREVIOUS_SUFFIX).into(), previous),
+        (format!("{}_change", column_id).into(), change),
+        (format!("{}_change_pct", column_id).into(), change_pct),
+    ]
+}
+
+/// Request filters with the top level date range replaced by the compared
+/// period.
+fn compared_filters(
+    filters: &Filter,
+    comparison: Comparison,
+) -> Result<Filter, Error> {
+    let Filter::And { value } = filters else {
+        return Err(Error::MissingFilter("Expected And filter".to_string()));
+    };
+    let bound = |date: &String| {
+        Date::parse(date).ok_or(Error::InvalidValue(
+            DATE_COLUMN.to_string(),
+            date.to_string(),
+        ))
+    };
+    let mut start = None;
+    let mut end = None;
+    for filter in value.iter() {
+        match filter {
+            Filter::Gte { column, value } if column == DATE_COLUMN => {
+                start = Some(bound(value)?)
+            }
+            Filter::Lt { column, value } if column == DATE_COLUMN => {
+                end = Some(bound(value)?)
+            }
+            _ => {}
+        }
+    }
+    let (Some(start), Some(end)) = (start, end) else {
+        return Err(Error::MissingFilter("date range".to_string()));
+    };
+    let (start, end) = comparison.period(start, end);
+    Ok(Filter::And {
+        value: value
+            .iter()
+            .map(|filter| match filter {
+                Filter::Gte { column, .. } if column == DATE_COLUMN => {
+                    Filter::Gte {
+                        column: column.clone(),
+                        value: start.to_string(),
+                    }
+                }
+                Filter::Lt { column, .. } if column == DATE_COLUMN => {
+                    Filter::Lt {
+                        column: column.clone(),
+                        value: end.to_string(),
+                    }
+                }
+                other => other.clone(),
+            })
+            .collect(),
+    })
+}
+
+fn conjunction(items: Vec<SqlAst>) -> Option<Box<SqlAst>> {
+    match items.is_empty() {
+        true => None,
+        false => Some(Box::new(SqlAst::Logical {
+            items,
+            variant: LogicalVariant::And,
+        })),
+    }
+}
+
+/// Restricts the final query to the rows after the cursor position:
+/// `k1 > v1 OR k1 = v1 AND k2 > v2 OR ...`, with `<` for descending keys.
+/// NULL keys are after every value when sorted last, before otherwise.
+fn keyset(
+    cursor: &str,
+    keys: &[SortKey],
+    outputs: &[(Arc<str>, SqlAst)],
+) -> Result<SqlAst, Error> {
+    let values = Cursor::decode(cursor)
+        .filter(|c| c.values.len() == keys.len() && !keys.is_empty())
+        .ok_or(Error::InvalidCursor(cursor.to_string()))?
+        .values
+        .iter()
+        .zip(keys.iter())
+        .map(|(value, key)| match value {
+            Some(value) => key.parse(value).map(Some),
+            None => Some(None),
+        })
+        .collect::<Option<Vec<Option<Literal>>>>()
+        .ok_or(Error::InvalidCursor(cursor.to_string()))?;
+    let expression = |key: &SortKey| -> SqlAst {
+        let (_, value) = outputs
+            .iter()
+            .find(|(id, _)| *id == key.column_id)
+            .expect("sort keys are output columns");
+        value.clone()
+    };
+    // The keyset filters the query computing the window functions, which
+    // would then only see the rows after the cursor.
+    if let Some((id, _)) = outputs
+        .iter()
+        .find(|(_, value)| !matches!(value, SqlAst::Scalar(_)))
+    {
+        return Err(Error::InvalidPagination(format!(
+            "Cursor cannot be combined with {}",
+            id
+        )));
+    }
+    let is_null = |key: &SortKey, negated: bool| SqlAst::IsNull {
+        value: Box::new(expression(key)),
+        negated,
+    };
+    let mut alternatives = vec![];
+    for (index, key) in keys.iter().enumerate() {
+        let after = match (&values[index], key.nulls_last()) {
+            (Some(value), nulls_last) => {
+                let beyond = SqlAst::Comparison {
+                    left: Box::new(expression(key)),
+                    operator: match key.direction {
+                        SortDirection::Asc => Operator::Greater,
+                        SortDirection::Desc => Operator::Less,
+                    },
+                    right: Box::new(SqlAst::Literal(value.clone())),
+                };
+                match nulls_last {
+                    true => SqlAst::Logical {
+                        items: vec![beyond, is_null(key, false)],
+                        variant: LogicalVariant::Or,
+                    },
+                    false => beyond,
+                }
+            }
+            // Nothing sorts after a NULL key sorted last.
+            (None, true) => continue,
+            (None, false) => is_null(key, true),
+        };
+        let mut items: Vec<SqlAst> = keys[..index]
+            .iter()
+            .zip(values.iter())
+            .map(|(previous, value)| match value {
+                Some(value) => SqlAst::Comparison {
+                    left: Box::new(expression(previous)),
+                    operator: Operator::Equal,
+                    right: Box::new(SqlAst::Literal(value.clone())),
+                },
+                None => is_null(previous, false),
+            })
+            .collect();
+        items.push(after);
+        alternatives.push(SqlAst::Logical {
+            items,
+            variant: LogicalVariant::And,
+        });
+    }
+    Ok(match alternatives.is_empty() {
+        true => SqlAst::Boolean(false),
+        false => SqlAst::Logical {
+            items: alternatives,
+            variant: LogicalVariant::Or,
+        },
+    })
+}
+
+/// Start of an hourly bucket, `YYYY-MM-DD HH:00:00`.
+fn hour_literal(value: &str) -> Option<Literal> {
+    let (date, time) = value.split_once(' ')?;
+    let hour = time.strip_suffix(":00:00")?;
+    let valid = Date::parse(date).is_some()
+        && hour.len() == 2
+        && hour.bytes().all(|b| b.is_ascii_digit())
+        && hour < "24";
+    valid.then(|| Literal::DateTime(value.into()))
+}
+
+/// Aggregates restricted to a set of grouping columns cannot be broken
+/// down by any other grouping.
+fn check_groupings(
+    columns: &[Column],
+    groupings: &[&Column],
+) -> Result<(), Error> {
+    for column in columns.iter() {
+        let Some(compatible) = &column.compatible_groupings else {
+            continue;
+        };
+        for grouping in groupings.iter() {
+            if !compatible.contains(&grouping.column_id) {
+                return Err(Error::IncompatibleGrouping(
+                    column.column_id.to_string(),
+                    grouping.column_id.to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every report has to bound the date column from both sides, so the fact
+/// table is never scanned in full.
+fn require_date_range(filters: &Filter) -> Result<(), Error> {
+    let Filter::And { value } = filters else {
+        return Err(Error::MissingFilter("Expected And filter".to_string()));
+    };
+    if !value.iter().any(
+        |f| matches!(f, Filter::Gte { column, .. } if column == DATE_COLUMN),
+    ) {
+        return Err(Error::MissingFilter("start_date".to_string()));
+    }
+    if !value.iter().any(
+        |f| matches!(f, Filter::Lt { column, .. } if column == DATE_COLUMN),
+    ) {
+        return Err(Error::MissingFilter("end_date".to_string()));
+    }
+    Ok(())
+}
+
+/// Pivot value as an expression, dates as `date('YYYY-MM-DD')`.
+fn literal_expression(literal: Literal) -> Expression {
+    match literal {
+        Literal::Integer(value) => Expression::Number(value.to_string().into()),
+        Literal::Decimal(value) => Expression::Number(value),
+        Literal::Text(value) | Literal::DateTime(value) => {
+            Expression::String(value)
+        }
+        Literal::Date(date) | Literal::Timestamp { date, .. } => {
+            Expression::Function {
+                name: "date".into(),
+                args: vec![Expression::String(date)],
+            }
+        }
+    }
+}
+
+fn literal(column: &Column, value: &str) -> Result<Literal, Error> {
+    column
+        .data_type
+        .parse_literal(value)
+        .ok_or(Error::InvalidValue(
+            column.column_id.to_string(),
+            value.to_string(),
+        ))
+}
+
+/// Date filters of a bucketed report have to start a bucket, otherwise the
+/// first and last buckets would only be partially counted.
+fn require_aligned_dates(
+    filters: &Filter,
+    granularity: Granularity,
+) -> Result<(), Error> {
+    let Filter::And { value } = filters else {
+        return Err(Error::MissingFilter("Expected And filter".to_string()));
+    };
+    value
+        .iter()
+        .try_for_each(|filter| require_aligned_date(filter, granularity))
+}
+
+fn require_aligned_date(
+    filter: &Filter,
+    granularity: Granularity,
+) -> Result<(), Error> {
+    let date = match filter {
+        Filter::And { value } | Filter::Or { value } => {
+            return value
+                .iter()
+                .try_for_each(|f| require_aligned_date(f, granularity))
+        }
+        Filter::Eq { column, value }
+        | Filter::Lt { column, value }
+        | Filter::Lte { column, value }
+        | Filter::Gt { column, value }
+        | Filter::Gte { column, value }
+            if column == DATE_COLUMN =>
+        {
+            value
+        }
+        _ => return Ok(()),
+    };
+    match granularity.is_aligned(date) {
+        true => Ok(()),
+        false => Err(Error::UnalignedDate(
+            date.to_string(),
+            granularity.unit().to_string(),
+        )),
+    }
+}
+
+/// Whether the date filters select whole buckets of dates stored at the
+/// `stored` granularity: range bounds have to start a bucket, and only
+/// stored days can be compared to a day by equality or an inclusive bound.
+fn selects_stored_buckets(filter: &Filter, stored: Granularity) -> bool {
+    match filter {
+        Filter::And { value } | Filter::Or { value } => {
+            value.iter().all(|f| selects_stored_buckets(f, stored))
+        }
+        Filter::Gte { column, value } | Filter::Lt { column, value }
+            if column == DATE_COLUMN =>
+        {
+            stored.is_aligned(value)
+        }
+        Filter::Eq { column, .. }
+        | Filter::Lte { column, .. }
+        | Filter::Gt { column, .. }
+            if column == DATE_COLUMN =>
+        {
+            stored == Granularity::Day
+        }
+        _ => true,
+    }
+}
+
+/// Rewrites the date filters of a bucketed report into half-open ranges of
+/// whole buckets, as the fact timestamp is compared rather than its bucket:
+/// `= d` becomes `>= d AND < next`, `<= d` becomes `< next` and `> d`
+/// becomes `>= next`, `next` starting the bucket after `d`.
+fn bucket_bounds(
+    filter: &Filter,
+    granularity: Granularity,
+) -> Result<Filter, Error> {
+    let next = |date: &String| {
+        Date::parse(date)
+            .map(|date| granularity.next_bucket(date).to_string())
+            .ok_or(Error::InvalidValue(
+                DATE_COLUMN.to_string(),
+                date.to_string(),
+            ))
+    };
+    let column = DATE_COLUMN.to_string();
+    Ok(match filter {
+        Filter::And { value } => {
+            let mut rewritten = vec![];
+            for filter in value.iter() {
+                // Ranges stay in the enclosing conjunction, where compared
+                // periods look for the date range.
+                let date_eq = matches!(
+                    filter,
+                    Filter::Eq { column, .. } if column == DATE_COLUMN
+                );
+                match bucket_bounds(filter, granularity)? {
+                    Filter::And { value } if date_eq => rewritten.extend(value),
+                    filter => rewritten.push(filter),
+                }
+            }
+            Filter::And { value: rewritten }
+        }
+        Filter::Or { value } => Filter::Or {
+            value: value
+                .iter()
+                .map(|f| bucket_bounds(f, granularity))
+                .collect::<Result<Vec<Filter>, Error>>()?,
+        },
+        Filter::Eq { column: c, value } if *c == column => Filter::And {
+            value: vec![
+                Filter::Gte {
+                    column: column.clone(),
+                    value: value.clone(),
+                },
+                Filter::Lt {
+                    value: next(value)?,
+                    column,
+                },
+            ],
+        },
+        Filter::Lte { column: c, value } if *c == column => Filter::Lt {
+            value: next(value)?,
+            column,
+        },
+        Filter::Gt { column: c, value } if *c == column => Filter::Gte {
+            value: next(value)?,
+            column,
+        },
+        other => other.clone(),
+    })
+}
+
+/// The fact timestamp and its bucket at the given granularity,
+/// `date_trunc('<unit>', from_unixtime(<fact>.<timestamp>[, '<timezone>']))`.
+fn time_expressions(
+    fact: &Table,
+    granularity: Granularity,
+    timezone: Option<Arc<str>>,
+) -> Result<Time, Error> {
+    let column = fact.timestamp.clone().ok_or(Error::InvalidSchema(
+        format!("Fact table {} has no timestamp column", fact.name),
+    ))?;
+    let unix_time = Expression::Column {
+        table: fact.name.clone(),
+        column,
+    };
+    let timestamp = Expression::Function {
+        name: "from_unixtime".into(),
+        args: vec![unix_time.clone()],
+    };
+    let local_timestamp = match &timezone {
+        Some(timezone) => Expression::Function {
+            name: "from_unixtime".into(),
+            args: vec![unix_time, Expression::String(timezone.clone())],
+        },
+        None => timestamp.clone(),
+    };
+    let bucket = Expression::Function {
+        name: "date_trunc".into(),
+        args: vec![
+            Expression::String(granularity.unit().into()),
+            local_timestamp,
+        ],
+    };
+    Ok(Time {
+        timestamp,
+        bucket,
+        timezone,
+    })
+}
+
+/// Tables the expressions of the queries refer to. Unqualified columns
+/// could belong to any table, so the referenced tables are then unknown.
+fn referenced_tables<'a>(
+    queries: impl IntoIterator<Item = &'a SqlAst>,
+) -> Option<Vec<Arc<str>>> {
+    let mut tables: Vec<Arc<str>> = vec![];
+    for query in queries {
+        for expression in query.expressions() {
+            if !expression.identifiers().is_empty() {
+                return None;
+            }
+            for table in expression.tables() {
+                if !tables.contains(&table) {
+                    tables.push(table);
+                }
+            }
+        }
+    }
+    Some(tables)
+}
+
+fn join_column(table: &Table) -> Result<&str, Error> {
+    table.column.as_deref().ok_or(Error::InvalidSchema(format!(
+        "Table {} has no join column",
+        table.name
+    )))
+}
+
+fn qualified(table: &str, column: &str) -> SqlAst {
+    SqlAst::Column(format!("{}.{}", table, column).into())
+}
+
+fn equals(left: SqlAst, right: SqlAst) -> SqlAst {
+    SqlAst::Comparison {
+        left: Box::new(left),
+        operator: Operator::Equal,
+        right: Box::new(right),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arc;
+    use crate::domain::calendar::{Date, FixedClock};
+    use crate::domain::models::{
+        AggregateTable, Comparison, Policy, RelativeRange, ReportStatus, Totals,
+    };
+    use crate::domain::models::{
+        Column, ColumnType, Datasource, Filter, ReportRequest, TableType,
+    };
+
+    fn schema() -> Schema {
+        Schema {
+            name: arc!["default"],
+            tables: vec![
+                Table {
+                    name: arc!["fact_table"],
+                    table_type: TableType::Fact,
+                    column: None,
+                    timestamp: Some(arc!["ts"]),
+                    aggregate: None,
+                },
+                Table {
+                    name: arc!["campaign_hierarchy"],
+                    table_type: TableType::Hierarchy,
+                    column: Some(arc!["line_item_id"]),
+                    timestamp: None,
+                    aggregate: None,
+                },
+                Table {
+                    name: arc!["dim_campaign"],
+                    table_type: TableType::Dimension,
+                    column: Some(arc!["campaign_id"]),
+                    timestamp: None,
+                    aggregate: None,
+                },
+                Table {
+                    name: arc!["conversions"],
+                    table_type: TableType::Fact,
+                    column: None,
+                    timestamp: Some(arc!["conversion_ts"]),
+                    aggregate: None,
+                },
+            ],
+        }
+    }
+
+    /// Schema with daily and monthly aggregates of the fact table by
+    /// campaign.
+    fn aggregate_schema() -> Schema {
+        let aggregate =
+            |name: &str, granularity, measures: &[&str], rows| Table {
+                name: Arc::from(name),
+                table_type: TableType::Aggregate,
+                column: None,
+                timestamp: None,
+                aggregate: Some(AggregateTable {
+                    fact: arc!["fact_table"],
+                    groupings: vec![arc!["date"], arc!["campaign_id"]],
+                    granularity: Some(granularity),
+                    measures: measures.iter().map(|m| Arc::from(*m)).collect(),
+                    rows,
+                }),
+            };
+        let mut schema = schema();
+        schema.tables.push(aggregate(
+            "daily_campaign",
+            Granularity::Day,
+            &["sum_impressions", "sum_spend"],
+            100000,
+        ));
+        schema.tables.push(aggregate(
+            "monthly_campaign",
+            Granularity::Month,
+            &["sum_impressions"],
+            5000,
+        ));
+        schema
+    }
+
+    fn column(
+        column_id: &str,
+        expression: &str,
+        column_type: ColumnType,
+        data_type: DataType,
+    ) -> Column {
+        Column {
+            name: Arc::from(column_id.to_uppercase()),
+            column_id: Arc::from(column_id),
+            expression: Arc::from(expression),
+            column_type,
+            data_type,
+            compatible_groupings: None,
+        }
+    }
+
+    fn date_column() -> Column {
+        column(
+            "date",
+            "from_unixtime(fact_table.ts, 'YYYY-mm-dd')",
+            ColumnType::Grouping,
+            DataType::Date,
+        )
+    }
+
+    fn datasource() -> Datasource {
+        Datasource {
+            name: arc!["default"],
+            columns: vec![
+                date_column(),
+                column(
+                    "campaign_id",
+                    "campaign_hierarchy.campaign_id",
+                    ColumnType::Grouping,
+                    DataType::I32,
+                ),
+                column(
+                    "line_item_id",
+                    "fact_table.line_item_id",
+                    ColumnType::Grouping,
+                    DataType::I32,
+                ),
+                column(
+                    "sum_impressions",
+                    "sum(fact_table.impressions)",
+                    ColumnType::Aggregate,
+                    DataType::I64,
+                ),
+                column(
+                    "sum_spend",
+                    "sum(fact_table.spend)",
+                    ColumnType::Aggregate,
+                    DataType::Dec64,
+                ),
+                column(
+                    "cpm",
+                    "sum_spend/sum_impressions*1000",
+                    ColumnType::Formula,
+                    DataType::Dec64,
+                ),
+                column(
+                    "sum_conversions",
+                    "count(conversions.conversion_id)",
+                    ColumnType::Aggregate,
+                    DataType::I64,
+                ),
+                column(
+                    "cumulative_spend",
+                    "sum_spend",
+                    ColumnType::RunningSum,
+                    DataType::Dec64,
+                ),
+                column(
+                    "impression_share",
+                    "sum_impressions",
+                    ColumnType::ShareOfTotal,
+                    DataType::Dec64,
+                ),
+            ],
+            timezone: None,
+            policies: vec![],
+        }
+    }
+
+    fn date_range() -> Vec<Filter> {
+        vec![
+            Filter::Gte {
+                column: "date".to_string(),
+                value: "2020-01-01".to_string(),
+            },
+            Filter::Lt {
+                column: "date".to_string(),
+                value: "2021-01-01".to_string(),
+            },
+        ]
+    }
+
+    fn report_request(columns: &[&str]) -> ReportRequest {
+        ReportRequest {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            filters: Filter::And {
+                value: date_range(),
+            },
+            sort: vec![],
+            limit: None,
+            offset: None,
+            cursor: None,
+            granularity: None,
+            timezone: None,
+            compare: None,
+            totals: None,
+            top_n: None,
+            pivot: None,
+            datasource: None,
+        }
+    }
+
+    fn where_clause(ast: &SqlAst) -> String {
+        let SqlAst::Select { from, .. } = ast else {
+            panic!("Expected SqlAst::Select");
+        };
+        let mut from = from.as_ref();
+        while let SqlAst::Join { left, .. } = from {
+            from = left.as_ref();
+        }
+        // Without outer columns the aggregation is the whole plan.
+        let aggregation = match from {
+            SqlAst::Subquery(aggregation, _) => aggregation.as_ref(),
+            _ => ast,
+        };
+        let SqlAst::Select {
+            where_clause: Some(where_clause),
+            ..
+        } = aggregation
+        else {
+            panic!("Expected a WHERE clause");
+        };
+        SQLGenerator::new()
+            .generate_sql(where_clause)
+            .trim()
+            .to_string()
+    }
+
+    #[test]
+    fn test_plan_success() {
+        // Create a dummy Column for testing.
+        let column = Column {
+            name: std::sync::Arc::from("username"),
+            column_id: std::sync::Arc::from("username"),
+            expression: std::sync::Arc::from("username"),
+            column_type: ColumnType::Grouping,
+            data_type: DataType::Text,
+            compatible_groupings: None,
+        };
+
+        let datasource = Datasource {
+            name: std::sync::Arc::from("default"),
+            columns: vec![column, date_column()],
+            timezone: None,
+            policies: vec![],
+            // Add other required fields if necessary.
+        };
+
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid");
+
+        let request = ReportRequest {
+            columns: vec!["username".to_string()],
+            filters: Filter::And {
+                value: vec![
+                    Filter::Gte {
+                        column: "date".to_string(),
+                        value: "2020-01-01".to_string(),
+                    },
+                    Filter::Lt {
+                        column: "date".to_string(),
+                        value: "2021-01-01".to_string(),
+                    },
+                ],
+            },
+            sort: vec![],
+            limit: None,
+            offset: None,
+            cursor: None,
+            granularity: None,
+            timezone: None,
+            compare: None,
+            totals: None,
+            top_n: None,
+            pivot: None,
+            datasource: None,
+            // Add other fields if ReportRequest requires them.
+        };
+
+        let result = planner.plan(request);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_plan_missing_filter() {
+        let datasource = Datasource {
+            name: std::sync::Arc::from("default"),
+            columns: vec![],
+            timezone: None,
+            policies: vec![],
+            // Add other required fields if necessary.
+        };
+
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid");
+
+        let request = ReportRequest {
+            columns: vec![],
+            filters: Filter::Or { value: vec![] },
+            sort: vec![],
+            limit: None,
+            offset: None,
+            cursor: None,
+            granularity: None,
+            timezone: None,
+            compare: None,
+            totals: None,
+            top_n: None,
+            pivot: None,
+            datasource: None,
+            // Add other fields if ReportRequest requires them.
+        };
+
+        let result = planner.plan(request);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_plan_joins_from_schema() {
+        let datasource = datasource();
+        let mut schema = schema();
+        schema.tables.push(Table {
+            name: arc!["dim_line_item"],
+            table_type: TableType::Dimension,
+            column: Some(arc!["line_item_id"]),
+            timestamp: None,
+            aggregate: None,
+        });
+        let planner = QueryPlanner::new(datasource, schema)
+            .expect("Datasource should be valid");
+
+        let request =
+            report_request(&["campaign_id", "line_item_id", "sum_impressions"]);
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT campaign_hierarchy.campaign_id AS campaign_id, fact_table.line_item_id AS line_item_id, sum(fact_table.impressions) AS sum_impressions FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' GROUP BY campaign_hierarchy.campaign_id, fact_table.line_item_id"
+        );
+    }
+
+    #[test]
+    fn test_plan_prunes_unused_joins() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let hierarchy_join = "FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE";
+
+        let ast = planner
+            .plan(report_request(&["date", "sum_impressions"]))
+            .expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.contains("FROM fact_table fact_table WHERE"));
+        assert!(!sql.contains("JOIN"));
+
+        let mut filters = date_range();
+        filters.push(Filter::Eq {
+            column: "campaign_id".to_string(),
+            value: "7".to_string(),
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["date", "sum_impressions"])
+        };
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.contains(hierarchy_join));
+
+        // Unqualified columns could come from any table.
+        let mut datasource = datasource();
+        for column in datasource.columns.iter_mut() {
+            if column.column_id.as_ref() == "line_item_id" {
+                column.expression = arc!["line_item_id"];
+            }
+        }
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid");
+        let ast = planner
+            .plan(report_request(&["line_item_id", "sum_impressions"]))
+            .expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.contains(
+            "FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id LEFT JOIN dim_campaign dim_campaign ON campaign_hierarchy.campaign_id = dim_campaign.campaign_id WHERE"
+        ));
+    }
+
+    #[test]
+    fn test_plan_joins_dimensions_into_aggregation() {
+        let mut datasource = datasource();
+        datasource.columns.push(column(
+            "campaign_name",
+            "dim_campaign.name",
+            ColumnType::Grouping,
+            DataType::Text,
+        ));
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Eq {
+            column: "campaign_name".to_string(),
+            value: "launch".to_string(),
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["campaign_name", "sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT dim_campaign.name AS campaign_name, sum(fact_table.impressions) AS sum_impressions FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id LEFT JOIN dim_campaign dim_campaign ON campaign_hierarchy.campaign_id = dim_campaign.campaign_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' AND dim_campaign.name = 'launch' GROUP BY dim_campaign.name"
+        );
+    }
+
+    #[test]
+    fn test_planner_for_requested_datasource() {
+        let registry = DatasourceRegistry::new(vec![
+            datasource(),
+            Datasource {
+                name: arc!["video"],
+                ..datasource()
+            },
+        ])
+        .expect("Datasources should be valid");
+        let request = ReportRequest {
+            datasource: Some("video".to_string()),
+            ..report_request(&["date", "sum_impressions"])
+        };
+
+        let planner = QueryPlanner::for_request(&registry, schema(), &request)
+            .expect("Datasource should resolve");
+        assert!(planner.plan(request.clone()).is_ok());
+
+        let other = ReportRequest {
+            datasource: Some("default".to_string()),
+            ..request.clone()
+        };
+        assert!(matches!(
+            planner.plan(other),
+            Err(Error::DatasourceMismatch(requested, planner))
+                if requested == "default" && planner == "video"
+        ));
+
+        let missing = ReportRequest {
+            datasource: Some("audio".to_string()),
+            ..request.clone()
+        };
+        assert!(matches!(
+            QueryPlanner::for_request(&registry, schema(), &missing),
+            Err(Error::Datasource(RegistryError::UnknownDatasource(_)))
+        ));
+
+        let unnamed = ReportRequest {
+            datasource: None,
+            ..request
+        };
+        assert!(matches!(
+            QueryPlanner::for_request(&registry, schema(), &unnamed),
+            Err(Error::Datasource(RegistryError::AmbiguousDatasource(_)))
+        ));
+    }
+
+    #[test]
+    fn test_plan_restricts_rows_to_principal() {
+        let datasource = Datasource {
+            policies: vec![Policy {
+                column: arc!["campaign_id"],
+                attribute: arc!["campaigns"],
+            }],
+            ..datasource()
+        };
+        let principal = Principal {
+            name: "agency".to_string(),
+            attributes: HashMap::from([(
+                "campaigns".to_string(),
+                vec!["1".to_string(), "2".to_string()],
+            )]),
+        };
+        let mut request = report_request(&["campaign_id", "sum_impressions"]);
+        let Filter::And { value } = &mut request.filters else {
+            panic!("Expected And filter");
+        };
+        // A request filter cannot widen the rows the policy grants.
+        value.push(Filter::Or {
+            value: vec![
+                Filter::Eq {
+                    column: "campaign_id".to_string(),
+                    value: "3".to_string(),
+                },
+                Filter::Gte {
+                    column: "campaign_id".to_string(),
+                    value: "0".to_string(),
+                },
+            ],
+        });
+
+        let planner = QueryPlanner::new(datasource.clone(), schema())
+            .expect("Datasource should be valid");
+        assert!(matches!(
+            planner.plan(request.clone()),
+            Err(Error::Unauthorized(_))
+        ));
+
+        let planner = planner.with_principal(principal.clone());
+        let ast = planner.plan(request.clone()).expect("Should plan");
+        assert_eq!(
+            where_clause(&ast),
+            "from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' AND (campaign_hierarchy.campaign_id = 3 OR campaign_hierarchy.campaign_id >= 0) AND (campaign_hierarchy.campaign_id = 1 OR campaign_hierarchy.campaign_id = 2)"
+        );
+
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid")
+            .with_principal(Principal {
+                attributes: HashMap::new(),
+                ..principal
+            });
+        assert!(matches!(planner.plan(request), Err(Error::Unauthorized(_))));
+    }
+
+    #[test]
+    fn test_plan_restricts_rows_by_dimension_column() {
+        let mut datasource = Datasource {
+            policies: vec![Policy {
+                column: arc!["advertiser_id"],
+                attribute: arc!["advertisers"],
+            }],
+            ..datasource()
+        };
+        datasource.columns.push(column(
+            "advertiser_id",
+            "dim_campaign.advertiser_id",
+            ColumnType::Grouping,
+            DataType::I64,
+        ));
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid")
+            .with_principal(Principal {
+                name: "advertiser".to_string(),
+                attributes: HashMap::from([(
+                    "advertisers".to_string(),
+                    vec!["5".to_string()],
+                )]),
+            });
+
+        let ast = planner
+            .plan(report_request(&["date", "sum_impressions"]))
+            .expect("Should plan");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT from_unixtime(fact_table.ts, 'YYYY-mm-dd') AS date, sum(fact_table.impressions) AS sum_impressions FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id LEFT JOIN dim_campaign dim_campaign ON campaign_hierarchy.campaign_id = dim_campaign.campaign_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' AND dim_campaign.advertiser_id = 5 GROUP BY from_unixtime(fact_table.ts, 'YYYY-mm-dd')"
+        );
+    }
+
+    #[test]
+    fn test_plan_without_fact_table() {
+        let datasource = Datasource {
+            name: arc!["default"],
+            columns: vec![],
+            timezone: None,
+            policies: vec![],
+        };
+        let schema = Schema {
+            name: arc!["default"],
+            tables: vec![],
+        };
+        let planner = QueryPlanner::new(datasource, schema)
+            .expect("Datasource should be valid");
+
+        let request = report_request(&[]);
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidSchema(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_compiles_nested_filters() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Or {
+            value: vec![
+                Filter::Eq {
+                    column: "campaign_id".to_string(),
+                    value: "7".to_string(),
+                },
+                Filter::And {
+                    value: vec![
+                        Filter::Gte {
+                            column: "line_item_id".to_string(),
+                            value: "100".to_string(),
+                        },
+                        Filter::Lte {
+                            column: "line_item_id".to_string(),
+                            value: "200".to_string(),
+                        },
+                    ],
+                },
+            ],
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        assert_eq!(
+            where_clause(&ast),
+            "from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' AND (campaign_hierarchy.campaign_id = 7 OR fact_table.line_item_id >= 100 AND fact_table.line_item_id <= 200)"
+        );
+    }
+
+    #[test]
+    fn test_plan_unknown_filter_column() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Gt {
+            column: "advertiser_id".to_string(),
+            value: "1".to_string(),
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&[])
+        };
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::ColumnNotFound(column)) if column == "advertiser_id"
+        ));
+    }
+
+    #[test]
+    fn test_plan_empty_logical_filter() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Or { value: vec![] });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&[])
+        };
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidFilter(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_formulas_over_facts() {
+        let mut datasource = datasource();
+        datasource.columns.push(column(
+            "cpm_cents",
+            "cpm * 100",
+            ColumnType::Formula,
+            DataType::Dec64,
+        ));
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid");
+        let request = report_request(&["date", "cpm_cents"]);
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT facts.date AS date, facts.sum_spend / facts.sum_impressions * 1000 * 100 AS cpm_cents FROM (SELECT from_unixtime(fact_table.ts, 'YYYY-mm-dd') AS date, sum(fact_table.spend) AS sum_spend, sum(fact_table.impressions) AS sum_impressions FROM fact_table fact_table WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' GROUP BY from_unixtime(fact_table.ts, 'YYYY-mm-dd')) facts"
+        );
+    }
+
+    #[test]
+    fn test_plan_parenthesizes_inlined_formulas() {
+        let mut datasource = datasource();
+        datasource.columns.push(column(
+            "total",
+            "sum_spend + sum_impressions",
+            ColumnType::Formula,
+            DataType::Dec64,
+        ));
+        datasource.columns.push(column(
+            "spend_share",
+            "sum_spend / total",
+            ColumnType::Formula,
+            DataType::Dec64,
+        ));
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid");
+
+        let ast = planner
+            .plan(report_request(&["spend_share"]))
+            .expect("Planning should succeed");
+        let SqlAst::Select { columns, .. } = ast else {
+            panic!("Expected SqlAst::Select");
+        };
+        let sql = SQLGenerator::new().generate_sql(&columns[0]);
+        assert_eq!(
+            sql.trim(),
+            "facts.sum_spend / (facts.sum_spend + facts.sum_impressions) AS spend_share"
+        );
+    }
+
+    #[test]
+    fn test_plan_formula_cycle() {
+        let mut datasource = datasource();
+        datasource.columns.push(column(
+            "a",
+            "b + sum_spend",
+            ColumnType::Formula,
+            DataType::Dec64,
+        ));
+        datasource.columns.push(column(
+            "b",
+            "a * 2",
+            ColumnType::Formula,
+            DataType::Dec64,
+        ));
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid");
+        let request = report_request(&["a"]);
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::FormulaCycle(cycle)) if cycle == vec!["a", "b", "a"]
+        ));
+    }
+
+    #[test]
+    fn test_planner_rejects_invalid_datasource() {
+        let mut datasource = datasource();
+        datasource.columns.push(column(
+            "ctr",
+            "sum_clicks / sum_impressions",
+            ColumnType::Formula,
+            DataType::Dec64,
+        ));
+
+        assert!(matches!(
+            QueryPlanner::new(datasource, schema()),
+            Err(Error::InvalidDatasource(errors))
+                if errors == vec![ValidationError::MissingReference(
+                    "ctr".to_string(),
+                    "sum_clicks".to_string(),
+                )]
+        ));
+    }
+
+    #[test]
+    fn test_plan_groups_by_requested_columns() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = report_request(&["date", "sum_impressions"]);
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql
+            .ends_with("GROUP BY from_unixtime(fact_table.ts, 'YYYY-mm-dd')"));
+    }
+
+    #[test]
+    fn test_plan_without_groupings() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = report_request(&["sum_impressions"]);
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(!sql.contains("GROUP BY"));
+    }
+
+    #[test]
+    fn test_plan_incompatible_grouping() {
+        let mut datasource = datasource();
+        datasource.columns.push(Column {
+            compatible_groupings: Some(vec![arc!["date"]]),
+            ..column(
+                "unique_users",
+                "approx_distinct(fact_table.user_id)",
+                ColumnType::Aggregate,
+                DataType::I64,
+            )
+        });
+        datasource.columns.push(column(
+            "impressions_per_user",
+            "sum_impressions / unique_users",
+            ColumnType::Formula,
+            DataType::Dec64,
+        ));
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid");
+
+        assert!(planner
+            .plan(report_request(&["date", "unique_users"]))
+            .is_ok());
+        assert!(matches!(
+            planner.plan(report_request(&["campaign_id", "impressions_per_user"])),
+            Err(Error::IncompatibleGrouping(aggregate, grouping))
+                if aggregate == "unique_users" && grouping == "campaign_id"
+        ));
+    }
+
+    #[test]
+    fn test_plan_sort() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            sort: vec![
+                Order::Desc {
+                    column: "cpm".to_string(),
+                    nulls: Some(Nulls::Last),
+                },
+                Order::Asc {
+                    column: "date".to_string(),
+                    nulls: None,
+                },
+            ],
+            ..report_request(&["date", "cpm"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.ends_with(" ORDER BY cpm DESC NULLS LAST, date ASC"));
+    }
+
+    #[test]
+    fn test_plan_sort_column_not_requested() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            sort: vec![Order::Asc {
+                column: "date".to_string(),
+                nulls: None,
+            }],
+            ..report_request(&["sum_impressions"])
+        };
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::SortColumnNotRequested(column)) if column == "date"
+        ));
+    }
+
+    #[test]
+    fn test_plan_limit_offset() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            sort: vec![Order::Desc {
+                column: "sum_impressions".to_string(),
+                nulls: None,
+            }],
+            limit: Some(100),
+            offset: Some(300),
+            ..report_request(&["date", "line_item_id", "sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.ends_with(
+            " ORDER BY sum_impressions DESC NULLS FIRST, date ASC NULLS LAST, line_item_id ASC NULLS LAST LIMIT 100 OFFSET 300"
+        ));
+    }
+
+    #[test]
+    fn test_plan_keyset_cursor() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let cursor = Cursor {
+            values: vec![
+                Some("5000".to_string()),
+                Some("2020-03-01".to_string()),
+            ],
+        };
+        let request = ReportRequest {
+            sort: vec![Order::Desc {
+                column: "sum_impressions".to_string(),
+                nulls: None,
+            }],
+            limit: Some(100),
+            cursor: Some(cursor.encode()),
+            ..report_request(&["date", "sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.ends_with(
+            " facts WHERE (facts.sum_impressions < 5000 OR facts.sum_impressions = 5000 AND (facts.date > DATE '2020-03-01' OR facts.date IS NULL)) ORDER BY sum_impressions DESC NULLS FIRST, date ASC NULLS LAST LIMIT 100"
+        ));
+    }
+
+    #[test]
+    fn test_plan_keyset_cursor_null_keys() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = |nulls: Nulls, values: Vec<Option<&str>>| {
+            let cursor = Cursor {
+                values: values
+                    .into_iter()
+                    .map(|v| v.map(String::from))
+                    .collect(),
+            };
+            ReportRequest {
+                sort: vec![Order::Desc {
+                    column: "sum_impressions".to_string(),
+                    nulls: Some(nulls),
+                }],
+                limit: Some(100),
+                cursor: Some(cursor.encode()),
+                ..report_request(&["date", "sum_impressions"])
+            }
+        };
+        let sql = |request: ReportRequest| {
+            let ast = planner.plan(request).expect("Planning should succeed");
+            SQLGenerator::new().generate_sql(&ast)
+        };
+
+        let after_value =
+            sql(request(Nulls::Last, vec![Some("5000"), Some("2020-03-01")]));
+        assert!(after_value.ends_with(
+            " facts WHERE (facts.sum_impressions < 5000 OR facts.sum_impressions IS NULL OR facts.sum_impressions = 5000 AND (facts.date > DATE '2020-03-01' OR facts.date IS NULL)) ORDER BY sum_impressions DESC NULLS LAST, date ASC NULLS LAST LIMIT 100"
+        ));
+        // Only rows with a NULL key remain, the date part of the keyset
+        // being pushed into the aggregation.
+        let after_last_null =
+            sql(request(Nulls::Last, vec![None, Some("2020-03-01")]));
+        assert!(after_last_null.contains(
+            " AND (from_unixtime(fact_table.ts, 'YYYY-mm-dd') > DATE '2020-03-01' OR from_unixtime(fact_table.ts, 'YYYY-mm-dd') IS NULL) GROUP BY"
+        ));
+        assert!(after_last_null.ends_with(
+            " facts WHERE facts.sum_impressions IS NULL ORDER BY sum_impressions DESC NULLS LAST, date ASC NULLS LAST LIMIT 100"
+        ));
+        let after_first_null =
+            sql(request(Nulls::First, vec![None, Some("2020-03-01")]));
+        assert!(after_first_null.ends_with(
+            " facts WHERE (facts.sum_impressions IS NOT NULL OR facts.sum_impressions IS NULL AND (facts.date > DATE '2020-03-01' OR facts.date IS NULL)) ORDER BY sum_impressions DESC NULLS FIRST, date ASC NULLS LAST LIMIT 100"
+        ));
+    }
+
+    #[test]
+    fn test_plan_keyset_cursor_hourly() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = |value: &str| ReportRequest {
+            granularity: Some(Granularity::Hour),
+            sort: vec![Order::Asc {
+                column: "date".to_string(),
+                nulls: None,
+            }],
+            limit: Some(100),
+            cursor: Some(
+                Cursor {
+                    values: vec![Some(value.to_string())],
+                }
+                .encode(),
+            ),
+            ..report_request(&["date", "sum_impressions"])
+        };
+
+        let ast = planner
+            .plan(request("2020-03-01 13:00:00"))
+            .expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.contains(
+            " AND (date_trunc('hour', from_unixtime(fact_table.ts)) > TIMESTAMP '2020-03-01 13:00:00' OR date_trunc('hour', from_unixtime(fact_table.ts)) IS NULL) GROUP BY"
+        ));
+        assert!(matches!(
+            planner.plan(request("2020-03-01")),
+            Err(Error::InvalidCursor(_))
+        ));
+        assert!(matches!(
+            planner.plan(request("2020-03-01 24:00:00")),
+            Err(Error::InvalidCursor(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_invalid_pagination() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let cursor = Cursor {
+            values: vec![Some("2020-03-01".to_string())],
+        };
+        let request = ReportRequest {
+            offset: Some(10),
+            cursor: Some(cursor.encode()),
+            ..report_request(&["date", "sum_impressions"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidPagination(_))
+        ));
+
+        let request = ReportRequest {
+            cursor: Some(cursor.encode()),
+            ..report_request(&["date", "line_item_id", "sum_impressions"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidCursor(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_rejects_mistyped_filter_value() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Eq {
+            column: "campaign_id".to_string(),
+            value: "7 OR 1 = 1".to_string(),
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["campaign_id"])
+        };
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidValue(column, value))
+                if column == "campaign_id" && value == "7 OR 1 = 1"
+        ));
+    }
+
+    #[test]
+    fn test_plan_granularity_buckets_date() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            granularity: Some(Granularity::Month),
+            ..report_request(&["date", "sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT date_trunc('month', from_unixtime(fact_table.ts)) AS date, sum(fact_table.impressions) AS sum_impressions FROM fact_table fact_table WHERE from_unixtime(fact_table.ts) >= DATE '2020-01-01' AND from_unixtime(fact_table.ts) < DATE '2021-01-01' GROUP BY date_trunc('month', from_unixtime(fact_table.ts))"
+        );
+    }
+
+    #[test]
+    fn test_plan_granularity_half_open_dates() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let text = |value: &str| value.to_string();
+        let request = |granularity, filters: Vec<Filter>| {
+            let mut value = date_range();
+            value.extend(filters);
+            ReportRequest {
+                granularity: Some(granularity),
+                filters: Filter::And { value },
+                ..report_request(&["date", "sum_impressions"])
+            }
+        };
+
+        let daily = request(
+            Granularity::Day,
+            vec![
+                Filter::Lte {
+                    column: text("date"),
+                    value: text("2020-06-30"),
+                },
+                Filter::Or {
+                    value: vec![
+                        Filter::Eq {
+                            column: text("date"),
+                            value: text("2020-02-29"),
+                        },
+                        Filter::Gt {
+                            column: text("date"),
+                            value: text("2020-05-31"),
+                        },
+                    ],
+                },
+            ],
+        );
+        let ast = planner.plan(daily).expect("Planning should succeed");
+        assert_eq!(
+            where_clause(&ast),
+            "from_unixtime(fact_table.ts) >= DATE '2020-01-01' AND from_unixtime(fact_table.ts) < DATE '2021-01-01' AND from_unixtime(fact_table.ts) < DATE '2020-07-01' AND (from_unixtime(fact_table.ts) >= DATE '2020-02-29' AND from_unixtime(fact_table.ts) < DATE '2020-03-01' OR from_unixtime(fact_table.ts) >= DATE '2020-06-01')"
+        );
+
+        let monthly = request(
+            Granularity::Month,
+            vec![Filter::Eq {
+                column: text("date"),
+                value: text("2020-12-01"),
+            }],
+        );
+        let ast = planner.plan(monthly).expect("Planning should succeed");
+        assert_eq!(
+            where_clause(&ast),
+            "from_unixtime(fact_table.ts) >= DATE '2020-01-01' AND from_unixtime(fact_table.ts) < DATE '2021-01-01' AND from_unixtime(fact_table.ts) >= DATE '2020-12-01' AND from_unixtime(fact_table.ts) < DATE '2021-01-01'"
+        );
+
+        let unaligned = request(
+            Granularity::Month,
+            vec![Filter::Or {
+                value: vec![Filter::Lte {
+                    column: text("date"),
+                    value: text("2020-06-30"),
+                }],
+            }],
+        );
+        assert!(matches!(
+            planner.plan(unaligned),
+            Err(Error::UnalignedDate(date, unit))
+                if date == "2020-06-30" && unit == "month"
+        ));
+    }
+
+    #[test]
+    fn test_plan_granularity_requires_aligned_dates() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            granularity: Some(Granularity::Week),
+            ..report_request(&["date", "sum_impressions"])
+        };
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::UnalignedDate(date, unit))
+                if date == "2020-01-01" && unit == "week"
+        ));
+    }
+
+    #[test]
+    fn test_plan_granularity_without_timestamp() {
+        let mut schema = schema();
+        schema.tables[0].timestamp = None;
+        let planner = QueryPlanner::new(datasource(), schema)
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            granularity: Some(Granularity::Day),
+            ..report_request(&["date"])
+        };
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidSchema(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_timezone_shifts_filters_and_buckets() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            granularity: Some(Granularity::Month),
+            timezone: Some("America/New_York".to_string()),
+            ..report_request(&["date", "sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT date_trunc('month', from_unixtime(fact_table.ts, 'America/New_York')) AS date, sum(fact_table.impressions) AS sum_impressions FROM fact_table fact_table WHERE from_unixtime(fact_table.ts) >= TIMESTAMP '2020-01-01 00:00:00 America/New_York' AND from_unixtime(fact_table.ts) < TIMESTAMP '2021-01-01 00:00:00 America/New_York' GROUP BY date_trunc('month', from_unixtime(fact_table.ts, 'America/New_York'))"
+        );
+    }
+
+    #[test]
+    fn test_plan_datasource_timezone_buckets_days() {
+        let datasource = Datasource {
+            timezone: Some(arc!["Europe/Berlin"]),
+            policies: vec![],
+            ..datasource()
+        };
+        let planner = QueryPlanner::new(datasource, schema())
+            .expect("Datasource should be valid");
+
+        let ast = planner
+            .plan(report_request(&["date"]))
+            .expect("Planning should succeed");
+        assert_eq!(
+            where_clause(&ast),
+            "from_unixtime(fact_table.ts) >= TIMESTAMP '2020-01-01 00:00:00 Europe/Berlin' AND from_unixtime(fact_table.ts) < TIMESTAMP '2021-01-01 00:00:00 Europe/Berlin'"
+        );
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.contains(
+            " GROUP BY date_trunc('day', from_unixtime(fact_table.ts, 'Europe/Berlin'))"
+        ));
+
+        let request = ReportRequest {
+            timezone: Some("UTC'; DROP TABLE fact_table".to_string()),
+            ..report_request(&["date"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidTimezone(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_resolves_relative_range() {
+        let today = Date::parse("2020-03-11").expect("Date should be valid");
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid")
+            .with_clock(Box::new(FixedClock(today)));
+        let request = ReportRequest {
+            filters: Filter::And {
+                value: vec![Filter::Relative {
+                    column: "date".to_string(),
+                    range: RelativeRange::MonthToDate,
+                }],
+            },
+            ..report_request(&["date"])
+        };
+
+        let ast = planner
+            .plan(request.clone())
+            .expect("Planning should succeed");
+        assert_eq!(
+            where_clause(&ast),
+            "from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-03-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2020-03-12'"
+        );
+
+        // Today is the date in the report timezone.
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid")
+            .with_clock(Box::new(ZonedClock));
+        let tokyo = ReportRequest {
+            timezone: Some("Asia/Tokyo".to_string()),
+            ..request.clone()
+        };
+        let ast = planner.plan(tokyo).expect("Planning should succeed");
+        assert_eq!(
+            where_clause(&ast),
+            "from_unixtime(fact_table.ts) >= TIMESTAMP '2020-04-01 00:00:00 Asia/Tokyo' AND from_unixtime(fact_table.ts) < TIMESTAMP '2020-04-02 00:00:00 Asia/Tokyo'"
+        );
+        let unknown = ReportRequest {
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+            ..request
+        };
+        assert!(matches!(
+            planner.plan(unknown),
+            Err(Error::InvalidTimezone(zone)) if zone == "Mars/Olympus_Mons"
+        ));
+    }
+
+    /// Clock at 2020-03-31 in UTC, when it is already April in Tokyo.
+    struct ZonedClock;
+
+    impl Clock for ZonedClock {
+        fn today(&self, timezone: Option<&str>) -> Option<Date> {
+            match timezone {
+                None => Date::new(2020, 3, 31),
+                Some("Asia/Tokyo") => Date::new(2020, 4, 1),
+                Some(_) => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_report_from_resolved_filters() {
+        let request = ReportRequest {
+            filters: Filter::And {
+                value: vec![Filter::Relative {
+                    column: "date".to_string(),
+                    range: RelativeRange::PreviousMonth,
+                }],
+            },
+            ..report_request(&["date"])
+        };
+        let created = Date::parse("2020-03-11").expect("Date should be valid");
+        let report = Report {
+            id: arc!["report"],
+            resolved_filters: datasource()
+                .resolve_filters(&request, &FixedClock(created)),
+            request,
+            pivot_columns: vec![],
+            status: ReportStatus::Pending,
+            metadata: None,
+        };
+        // Reruns months later still cover the month before creation.
+        let today = Date::parse("2020-06-15").expect("Date should be valid");
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid")
+            .with_clock(Box::new(FixedClock(today)));
+
+        let ast = planner
+            .plan_report(&report)
+            .expect("Planning should succeed");
+        assert_eq!(
+            where_clause(&ast),
+            "from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-02-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2020-03-01'"
+        );
+    }
+
+    #[test]
+    fn test_plan_compares_previous_period() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            compare: Some(Comparison::PreviousPeriod),
+            ..report_request(&["campaign_id", "sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        let aggregation = |start: &str, end: &str| {
+            format!(
+                "(SELECT campaign_hierarchy.campaign_id AS campaign_id, sum(fact_table.impressions) AS sum_impressions FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '{}' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '{}' GROUP BY campaign_hierarchy.campaign_id)",
+                start, end
+            )
+        };
+        assert_eq!(
+            sql.trim(),
+            format!(
+                "SELECT facts.campaign_id AS campaign_id, facts.sum_impressions AS sum_impressions, facts.sum_impressions_previous AS sum_impressions_previous, facts.sum_impressions - facts.sum_impressions_previous AS sum_impressions_change, 100.0 * (facts.sum_impressions - facts.sum_impressions_previous) / nullif(facts.sum_impressions_previous, 0) AS sum_impressions_change_pct FROM (SELECT coalesce(current_period.campaign_id, previous_period.campaign_id) AS campaign_id, current_period.sum_impressions AS sum_impressions, previous_period.sum_impressions AS sum_impressions_previous FROM {} current_period FULL JOIN {} previous_period ON current_period.campaign_id = previous_period.campaign_id) facts",
+                aggregation("2020-01-01", "2021-01-01"),
+                aggregation("2018-12-31", "2020-01-01"),
+            )
+        );
+    }
+
+    #[test]
+    fn test_plan_compares_formulas() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            compare: Some(Comparison::PreviousPeriod),
+            ..report_request(&["cpm"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let SqlAst::Select { columns, .. } = ast else {
+            panic!("Expected SqlAst::Select");
+        };
+        let sql = SQLGenerator::new().generate_sql(&columns[1]);
+        assert_eq!(
+            sql.trim(),
+            "facts.sum_spend_previous / facts.sum_impressions_previous * 1000 AS cpm_previous"
+        );
+    }
+
+    #[test]
+    fn test_plan_comparison_grouped_by_date() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            compare: Some(Comparison::PreviousPeriod),
+            ..report_request(&["date", "sum_impressions"])
+        };
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidComparison(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_rollup_totals() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            totals: Some(Totals::Rollup),
+            ..report_request(&[
+                "campaign_id",
+                "line_item_id",
+                "sum_impressions",
+            ])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT campaign_hierarchy.campaign_id AS campaign_id, fact_table.line_item_id AS line_item_id, sum(fact_table.impressions) AS sum_impressions, CASE grouping(campaign_hierarchy.campaign_id, fact_table.line_item_id) WHEN 0 THEN 'detail' WHEN 3 THEN 'total' ELSE 'subtotal' END AS row_type FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' GROUP BY ROLLUP (campaign_hierarchy.campaign_id, fact_table.line_item_id)"
+        );
+    }
+
+    #[test]
+    fn test_plan_total_levels() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            totals: Some(Totals::Levels(vec![
+                vec!["campaign_id".to_string()],
+                vec![],
+            ])),
+            ..report_request(&[
+                "campaign_id",
+                "line_item_id",
+                "sum_impressions",
+            ])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.contains(
+            " GROUP BY GROUPING SETS ((campaign_hierarchy.campaign_id, fact_table.line_item_id), (campaign_hierarchy.campaign_id), ())"
+        ));
+
+        let request = ReportRequest {
+            totals: Some(Totals::Levels(vec![vec!["date".to_string()]])),
+            ..report_request(&["campaign_id", "sum_impressions"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidTotals(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_totals_reject_cursor() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            totals: Some(Totals::Rollup),
+            limit: Some(100),
+            cursor: Some(
+                Cursor {
+                    values: vec![Some("3".to_string())],
+                }
+                .encode(),
+            ),
+            ..report_request(&["campaign_id", "sum_impressions"])
+        };
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidTotals(message))
+                if message == "Totals cannot be combined with a cursor"
+        ));
+    }
+
+    #[test]
+    fn test_plan_top_n_per_group() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            sort: vec![Order::Desc {
+                column: "sum_impressions".to_string(),
+                nulls: None,
+            }],
+            top_n: Some(TopN {
+                limit: 3,
+                per: vec!["campaign_id".to_string()],
+            }),
+            ..report_request(&[
+                "campaign_id",
+                "line_item_id",
+                "sum_impressions",
+            ])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.trim().starts_with(
+            "SELECT ranked.campaign_id AS campaign_id, ranked.line_item_id AS line_item_id, ranked.sum_impressions AS sum_impressions FROM (SELECT facts.campaign_id AS campaign_id, facts.line_item_id AS line_item_id, facts.sum_impressions AS sum_impressions, row_number() OVER (PARTITION BY facts.campaign_id ORDER BY facts.sum_impressions DESC) AS row_rank FROM (SELECT"
+        ));
+        assert!(sql.ends_with(
+            " ranked WHERE ranked.row_rank <= 3 ORDER BY sum_impressions DESC"
+        ));
+    }
+
+    #[test]
+    fn test_plan_invalid_top_n() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let sorted = |top_n: TopN| ReportRequest {
+            sort: vec![Order::Desc {
+                column: "sum_impressions".to_string(),
+                nulls: None,
+            }],
+            top_n: Some(top_n),
+            ..report_request(&["campaign_id", "sum_impressions"])
+        };
+
+        for request in [
+            sorted(TopN {
+                limit: 0,
+                per: vec![],
+            }),
+            sorted(TopN {
+                limit: 3,
+                per: vec!["line_item_id".to_string()],
+            }),
+            sorted(TopN {
+                limit: 3,
+                per: vec!["sum_impressions".to_string()],
+            }),
+            ReportRequest {
+                sort: vec![],
+                ..sorted(TopN {
+                    limit: 3,
+                    per: vec![],
+                })
+            },
+        ] {
+            assert!(matches!(
+                planner.plan(request),
+                Err(Error::InvalidTopN(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_plan_running_sum() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            sort: vec![Order::Asc {
+                column: "date".to_string(),
+                nulls: None,
+            }],
+            ..report_request(&["date", "campaign_id", "cumulative_spend"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.trim().starts_with(
+            "SELECT facts.date AS date, facts.campaign_id AS campaign_id, sum(facts.sum_spend) OVER (PARTITION BY facts.campaign_id ORDER BY facts.date ASC ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW) AS cumulative_spend FROM (SELECT from_unixtime(fact_table.ts, 'YYYY-mm-dd') AS date, campaign_hierarchy.campaign_id AS campaign_id, sum(fact_table.spend) AS sum_spend FROM"
+        ));
+        assert!(sql.ends_with(" ORDER BY date ASC"));
+
+        let request = report_request(&["date", "cumulative_spend"]);
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidRunningSum(message))
+                if message == "cumulative_spend needs a sort order"
+        ));
+
+        let request = ReportRequest {
+            sort: vec![Order::Asc {
+                column: "cumulative_spend".to_string(),
+                nulls: None,
+            }],
+            ..report_request(&["date", "cumulative_spend"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidRunningSum(message))
+                if message == "cumulative_spend cannot be ordered by window column cumulative_spend"
+        ));
+    }
+
+    #[test]
+    fn test_plan_share_of_total() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = report_request(&["campaign_id", "impression_share"]);
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.trim().starts_with(
+            "SELECT facts.campaign_id AS campaign_id, 100.0 * facts.sum_impressions / nullif(sum(facts.sum_impressions) OVER (), 0) AS impression_share FROM (SELECT campaign_hierarchy.campaign_id AS campaign_id, sum(fact_table.impressions) AS sum_impressions FROM"
+        ));
+    }
+
+    #[test]
+    fn test_plan_rejects_window_column_filters_and_cursors() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Gt {
+            column: "impression_share".to_string(),
+            value: "10".to_string(),
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["campaign_id", "impression_share"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidFilter(_))
+        ));
+
+        let request = ReportRequest {
+            sort: vec![Order::Desc {
+                column: "impression_share".to_string(),
+                nulls: None,
+            }],
+            cursor: Some(
+                Cursor {
+                    values: vec![Some("10".to_string()), Some("7".to_string())],
+                }
+                .encode(),
+            ),
+            ..report_request(&["campaign_id", "impression_share"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidPagination(_))
+        ));
+
+        let request = ReportRequest {
+            sort: vec![Order::Asc {
+                column: "date".to_string(),
+                nulls: None,
+            }],
+            cursor: Some(
+                Cursor {
+                    values: vec![Some("2020-01-01".to_string())],
+                }
+                .encode(),
+            ),
+            ..report_request(&["date", "cumulative_spend"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidPagination(message))
+                if message == "Cursor cannot be combined with cumulative_spend"
+        ));
+
+        let request = ReportRequest {
+            totals: Some(Totals::Rollup),
+            ..report_request(&["campaign_id", "impression_share"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidTotals(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_pivot() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            granularity: Some(Granularity::Month),
+            pivot: Some(Pivot {
+                column: "date".to_string(),
+                values: vec![
+                    "2020-01-01".to_string(),
+                    "2020-02-01".to_string(),
+                ],
+            }),
+            ..report_request(&["campaign_id", "date", "sum_impressions"])
+        };
+
+        let ast = planner
+            .plan(request.clone())
+            .expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            planner
+                .explain(request)
+                .expect("Explain should succeed")
+                .pivot_columns
+                .iter()
+                .map(|c| c.column_id.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["sum_impressions_2020_01_01", "sum_impressions_2020_02_01"]
+        );
+        assert_eq!(
+            sql.trim(),
+            "SELECT campaign_hierarchy.campaign_id AS campaign_id, sum(CASE date_trunc('month', from_unixtime(fact_table.ts)) WHEN date('2020-01-01') THEN fact_table.impressions END) AS sum_impressions_2020_01_01, sum(CASE date_trunc('month', from_unixtime(fact_table.ts)) WHEN date('2020-02-01') THEN fact_table.impressions END) AS sum_impressions_2020_02_01 FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(fact_table.ts) >= DATE '2020-01-01' AND from_unixtime(fact_table.ts) < DATE '2021-01-01' GROUP BY campaign_hierarchy.campaign_id"
+        );
+    }
+
+    #[test]
+    fn test_plan_invalid_pivot() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let pivot = |column: &str, values: &[&str]| ReportRequest {
+            pivot: Some(Pivot {
+                column: column.to_string(),
+                values: values.iter().map(|v| v.to_string()).collect(),
+            }),
+            ..report_request(&[
+                "campaign_id",
+                "line_item_id",
+                "sum_impressions",
+            ])
+        };
+
+        for request in [
+            pivot("date", &["2020-01-01"]),
+            pivot("sum_impressions", &["1"]),
+            pivot("line_item_id", &[]),
+            pivot("line_item_id", &["1", "1"]),
+            ReportRequest {
+                sort: vec![Order::Desc {
+                    column: "sum_impressions".to_string(),
+                    nulls: None,
+                }],
+                ..pivot("line_item_id", &["1"])
+            },
+            ReportRequest {
+                columns: vec!["campaign_id".to_string(), "cpm".to_string()],
+                ..pivot("campaign_id", &["1"])
+            },
+        ] {
+            assert!(matches!(
+                planner.plan(request),
+                Err(Error::InvalidPivot(_))
+            ));
+        }
+        assert!(matches!(
+            planner.plan(pivot("line_item_id", &["one"])),
+            Err(Error::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_plan_drill_across() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Gt {
+            column: "sum_conversions".to_string(),
+            value: "0".to_string(),
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["campaign_id", "sum_spend", "sum_conversions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT coalesce(fact_table.campaign_id, conversions.campaign_id) AS campaign_id, fact_table.sum_spend AS sum_spend, conversions.sum_conversions AS sum_conversions FROM (SELECT campaign_hierarchy.campaign_id AS campaign_id, sum(fact_table.spend) AS sum_spend FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' GROUP BY campaign_hierarchy.campaign_id) fact_table FULL JOIN (SELECT campaign_hierarchy.campaign_id AS campaign_id, count(conversions.conversion_id) AS sum_conversions FROM conversions conversions LEFT JOIN campaign_hierarchy campaign_hierarchy ON conversions.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(conversions.conversion_ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(conversions.conversion_ts, 'YYYY-mm-dd') < DATE '2021-01-01' GROUP BY campaign_hierarchy.campaign_id) conversions ON fact_table.campaign_id = conversions.campaign_id WHERE conversions.sum_conversions > 0"
+        );
+    }
+
+    #[test]
+    fn test_plan_single_other_fact() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            granularity: Some(Granularity::Month),
+            ..report_request(&["date", "sum_conversions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.contains(
+            "SELECT date_trunc('month', from_unixtime(conversions.conversion_ts)) AS date, count(conversions.conversion_id) AS sum_conversions FROM conversions conversions WHERE"
+        ));
+
+        let request = ReportRequest {
+            compare: Some(Comparison::PreviousPeriod),
+            ..report_request(&["sum_spend", "sum_conversions"])
+        };
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidDrillAcross(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_uses_smallest_aggregate_table() {
+        let planner = QueryPlanner::new(datasource(), aggregate_schema())
+            .expect("Datasource should be valid");
+        let request = report_request(&["campaign_id", "sum_impressions"]);
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT monthly_campaign.campaign_id AS campaign_id, sum(monthly_campaign.sum_impressions) AS sum_impressions FROM monthly_campaign monthly_campaign WHERE monthly_campaign.date >= DATE '2020-01-01' AND monthly_campaign.date < DATE '2021-01-01' GROUP BY monthly_campaign.campaign_id"
+        );
+    }
+
+    #[test]
+    fn test_plan_buckets_aggregate_table_dates() {
+        let planner = QueryPlanner::new(datasource(), aggregate_schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            granularity: Some(Granularity::Quarter),
+            ..report_request(&["date", "cpm"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.contains(
+            "(SELECT date_trunc('quarter', daily_campaign.date) AS date, sum(daily_campaign.sum_spend) AS sum_spend, sum(daily_campaign.sum_impressions) AS sum_impressions FROM daily_campaign daily_campaign WHERE daily_campaign.date >= DATE '2020-01-01' AND"
+        ));
+
+        let request = ReportRequest {
+            granularity: Some(Granularity::Month),
+            ..report_request(&["date", "sum_impressions"])
+        };
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(sql.contains(
+            "SELECT monthly_campaign.date AS date, sum(monthly_campaign.sum_impressions) AS sum_impressions FROM monthly_campaign monthly_campaign"
+        ));
+    }
+
+    #[test]
+    fn test_plan_aggregate_table_compared_period() {
+        let planner = QueryPlanner::new(datasource(), aggregate_schema())
+            .expect("Datasource should be valid");
+        let request = ReportRequest {
+            filters: Filter::And {
+                value: vec![
+                    Filter::Gte {
+                        column: "date".to_string(),
+                        value: "2020-03-01".to_string(),
+                    },
+                    Filter::Lt {
+                        column: "date".to_string(),
+                        value: "2020-04-01".to_string(),
+                    },
+                ],
+            },
+            compare: Some(Comparison::PreviousPeriod),
+            ..report_request(&["campaign_id", "sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(!sql.contains("monthly_campaign"), "{}", sql);
+        assert!(
+            sql.contains(" FROM daily_campaign daily_campaign "),
+            "{}",
+            sql
+        );
+    }
+
+    #[test]
+    fn test_plan_aggregate_table_inclusive_dates() {
+        let planner = QueryPlanner::new(datasource(), aggregate_schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Lte {
+            column: "date".to_string(),
+            value: "2020-06-01".to_string(),
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["campaign_id", "sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert!(!sql.contains("monthly_campaign"), "{}", sql);
+        assert!(
+            sql.contains(" FROM daily_campaign daily_campaign "),
+            "{}",
+            sql
+        );
+    }
+
+    #[test]
+    fn test_plan_falls_back_to_fact_table() {
+        let planner = QueryPlanner::new(datasource(), aggregate_schema())
+            .expect("Datasource should be valid");
+        for request in [
+            report_request(&["line_item_id", "sum_impressions"]),
+            report_request(&["date", "sum_impressions"]),
+            ReportRequest {
+                granularity: Some(Granularity::Hour),
+                ..report_request(&["date", "sum_impressions"])
+            },
+            ReportRequest {
+                timezone: Some("Europe/Berlin".to_string()),
+                ..report_request(&["campaign_id", "sum_impressions"])
+            },
+        ] {
+            let ast = planner.plan(request).expect("Planning should succeed");
+            let sql = SQLGenerator::new().generate_sql(&ast);
+            assert!(sql.contains(" FROM fact_table fact_table "), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_plan_splits_filters_by_stage() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Gt {
+            column: "sum_impressions".to_string(),
+            value: "1000".to_string(),
+        });
+        filters.push(Filter::Or {
+            value: vec![
+                Filter::Eq {
+                    column: "campaign_id".to_string(),
+                    value: "7".to_string(),
+                },
+                Filter::Lt {
+                    column: "sum_spend".to_string(),
+                    value: "10".to_string(),
+                },
+            ],
+        });
+        filters.push(Filter::Gte {
+            column: "cpm".to_string(),
+            value: "2.5".to_string(),
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["campaign_id", "sum_impressions"])
+        };
+
+        let ast = planner.plan(request).expect("Planning should succeed");
+        let sql = SQLGenerator::new().generate_sql(&ast);
+        assert_eq!(
+            sql.trim(),
+            "SELECT facts.campaign_id AS campaign_id, facts.sum_impressions AS sum_impressions FROM (SELECT campaign_hierarchy.campaign_id AS campaign_id, sum(fact_table.impressions) AS sum_impressions, sum(fact_table.spend) AS sum_spend FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' GROUP BY campaign_hierarchy.campaign_id HAVING sum(fact_table.impressions) > 1000 AND (campaign_hierarchy.campaign_id = 7 OR sum(fact_table.spend) < 10)) facts WHERE facts.sum_spend / facts.sum_impressions * 1000 >= 2.5"
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let aggregate_filter = Filter::Gt {
+            column: "sum_impressions".to_string(),
+            value: "1000".to_string(),
+        };
+        let formula_filter = Filter::Gte {
+            column: "cpm".to_string(),
+            value: "2.5".to_string(),
+        };
+        let mut filters = date_range();
+        filters.push(aggregate_filter.clone());
+        filters.push(formula_filter.clone());
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["campaign_id", "sum_impressions"])
+        };
+
+        let ast = planner
+            .plan(request.clone())
+            .expect("Planning should succeed");
+        let explain = planner.explain(request).expect("Explain should succeed");
+        assert_eq!(
+            explain.columns,
+            vec![
+                ResolvedColumn {
+                    column_id: "campaign_id".to_string(),
+                    column_type: ColumnType::Grouping,
+                    data_type: DataType::I32,
+                    expression: "campaign_hierarchy.campaign_id".to_string(),
+                },
+                ResolvedColumn {
+                    column_id: "sum_impressions".to_string(),
+                    column_type: ColumnType::Aggregate,
+                    data_type: DataType::I64,
+                    expression: "sum(fact_table.impressions)".to_string(),
+                },
+            ]
+        );
+        assert_eq!(explain.tables, vec!["fact_table", "campaign_hierarchy"]);
+        assert_eq!(explain.joins.len(), 1);
+        assert_eq!(explain.joins[0].join_type, "LEFT JOIN");
+        assert_eq!(
+            explain.joins[0].on,
+            "fact_table.line_item_id = campaign_hierarchy.line_item_id"
+        );
+        assert_eq!(
+            explain.filters,
+            FilterSplit {
+                where_clause: date_range(),
+                having: vec![aggregate_filter],
+                outer_where: vec![formula_filter],
+            }
+        );
+        assert_eq!(explain.sql, SQLGenerator::new().generate_sql(&ast).trim());
+    }
+
+    #[test]
+    fn test_explain_filters_as_planned() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let aggregate_filter = Filter::Gt {
+            column: "sum_conversions".to_string(),
+            value: "0".to_string(),
+        };
+        let mut filters = date_range();
+        filters.push(aggregate_filter.clone());
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["campaign_id", "sum_spend", "sum_conversions"])
+        };
+        let explain = planner.explain(request).expect("Explain should succeed");
+        assert_eq!(
+            explain.filters,
+            FilterSplit {
+                where_clause: date_range(),
+                having: vec![],
+                outer_where: vec![aggregate_filter],
+            }
+        );
+
+        let request = ReportRequest {
+            filters: Filter::And {
+                value: vec![
+                    Filter::Gte {
+                        column: "date".to_string(),
+                        value: "2020-01-01".to_string(),
+                    },
+                    Filter::Lt {
+                        column: "date".to_string(),
+                        value: "2021-01-01".to_string(),
+                    },
+                    Filter::Lte {
+                        column: "date".to_string(),
+                        value: "2020-06-01".to_string(),
+                    },
+                ],
+            },
+            granularity: Some(Granularity::Month),
+            ..report_request(&["date", "sum_impressions"])
+        };
+        let explain = planner.explain(request).expect("Explain should succeed");
+        let mut bounds = date_range();
+        bounds.push(Filter::Lt {
+            column: "date".to_string(),
+            value: "2020-07-01".to_string(),
+        });
+        assert_eq!(explain.filters.where_clause, bounds);
+    }
+
+    #[test]
+    fn test_plan_aggregate_filter_on_ungrouped_column() {
+        let planner = QueryPlanner::new(datasource(), schema())
+            .expect("Datasource should be valid");
+        let mut filters = date_range();
+        filters.push(Filter::Or {
+            value: vec![
+                Filter::Eq {
+                    column: "line_item_id".to_string(),
+                    value: "7".to_string(),
+                },
+                Filter::Gt {
+                    column: "sum_impressions".to_string(),
+                    value: "1000".to_string(),
+                },
+            ],
+        });
+        let request = ReportRequest {
+            filters: Filter::And { value: filters },
+            ..report_request(&["date", "sum_impressions"])
+        };
+
+        assert!(matches!(
+            planner.plan(request),
+            Err(Error::InvalidFilter(_))
+        ));
     }
 }
@@ -1,19 +1,29 @@
-use std::rc::Rc;
+use crate::domain::expression::{BinaryOperator, Expression};
+use crate::domain::models::Literal;
+use std::sync::Arc;
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum SqlAst {
     Select {
         columns: Vec<SqlAst>,
         from: Box<SqlAst>,
         where_clause: Option<Box<SqlAst>>,
         group_by: Option<Vec<SqlAst>>,
+        having: Option<Box<SqlAst>>,
         order_by: Option<Vec<SqlAst>>,
     },
-    Table(Rc<str>, Rc<str>),
-    Subquery(Box<SqlAst>, Rc<str>),
-    Column(Rc<str>),
+    Table(Arc<str>, Arc<str>),
+    Subquery(Box<SqlAst>, Arc<str>),
+    Column(Arc<str>),
     ColumnAlias {
-        column: Rc<str>,
-        alias: Rc<str>,
+        column: Arc<str>,
+        alias: Arc<str>,
+    },
+    /// Parsed column expression.
+    Scalar(Expression),
+    ScalarAlias {
+        expression: Expression,
+        alias: Arc<str>,
     },
     Join {
         left: Box<SqlAst>,
@@ -22,19 +32,317 @@ pub enum SqlAst {
         on: Box<SqlAst>,
     },
     Expression(Box<SqlAst>),
-    Literal(String),
+    Literal(Literal),
+    /// `TRUE` or `FALSE`, left by folding constant predicates.
+    Boolean(bool),
     Comparison {
         left: Box<SqlAst>,
         operator: Operator,
         right: Box<SqlAst>,
     },
+    /// `value IS NULL`, or `IS NOT NULL` when negated.
+    IsNull {
+        value: Box<SqlAst>,
+        negated: bool,
+    },
     Logical {
         items: Vec<SqlAst>,
         variant: LogicalVariant,
     },
+    OrderBy {
+        expression: Box<SqlAst>,
+        direction: SortDirection,
+        nulls: Option<NullsOrder>,
+    },
+    Limit {
+        query: Box<SqlAst>,
+        limit: Option<u64>,
+        offset: Option<u64>,
+    },
+    /// GROUP BY element grouping by every prefix of the columns.
+    Rollup(Vec<SqlAst>),
+    /// GROUP BY element grouping by every subset of the columns.
+    Cube(Vec<SqlAst>),
+    GroupingSets(Vec<Vec<SqlAst>>),
+    /// `function OVER (PARTITION BY ... ORDER BY ... frame)`.
+    Window {
+        function: WindowFunction,
+        partition_by: Vec<SqlAst>,
+        order_by: Vec<SqlAst>,
+        frame: Option<Frame>,
+    },
+    Alias {
+        value: Box<SqlAst>,
+        alias: Arc<str>,
+    },
+    /// Function call over nodes that are not plain expressions, such as
+    /// window functions.
+    Function {
+        name: Arc<str>,
+        args: Vec<SqlAst>,
+    },
+    Binary {
+        left: Box<SqlAst>,
+        operator: BinaryOperator,
+        right: Box<SqlAst>,
+    },
+}
+
+impl SqlAst {
+    /// Rebuilds the tree top down, replacing every node for which `replace`
+    /// returns a node.
+    pub fn transform(
+        &self,
+        replace: &dyn Fn(&SqlAst) -> Option<SqlAst>,
+    ) -> SqlAst {
+        if let Some(replaced) = replace(self) {
+            return replaced;
+        }
+        let node = |ast: &SqlAst| Box::new(ast.transform(replace));
+        let list = |items: &[SqlAst]| -> Vec<SqlAst> {
+            items.iter().map(|item| item.transform(replace)).collect()
+        };
+        match self {
+            SqlAst::Select {
+                columns,
+                from,
+                where_clause,
+                group_by,
+                having,
+                order_by,
+            } => SqlAst::Select {
+                columns: list(columns),
+                from: node(from),
+                where_clause: where_clause.as_deref().map(node),
+                group_by: group_by.as_deref().map(list),
+                having: having.as_deref().map(node),
+                order_by: order_by.as_deref().map(list),
+            },
+            SqlAst::Subquery(query, alias) => {
+                SqlAst::Subquery(node(query), alias.clone())
+            }
+            SqlAst::Join {
+                left,
+                right,
+                join_type,
+                on,
+            } => SqlAst::Join {
+                left: node(left),
+                right: node(right),
+                join_type: *join_type,
+                on: node(on),
+            },
+            SqlAst::Expression(inner) => SqlAst::Expression(node(inner)),
+            SqlAst::Comparison {
+                left,
+                operator,
+                right,
+            } => SqlAst::Comparison {
+                left: node(left),
+                operator: *operator,
+                right: node(right),
+            },
+            SqlAst::IsNull { value, negated } => SqlAst::IsNull {
+                value: node(value),
+                negated: *negated,
+            },
+            SqlAst::Logical { items, variant } => SqlAst::Logical {
+                items: list(items),
+                variant: *variant,
+            },
+            SqlAst::OrderBy {
+                expression,
+                direction,
+                nulls,
+            } => SqlAst::OrderBy {
+                expression: node(expression),
+                direction: *direction,
+                nulls: *nulls,
+            },
+            SqlAst::Limit {
+                query,
+                limit,
+                offset,
+            } => SqlAst::Limit {
+                query: node(query),
+                limit: *limit,
+                offset: *offset,
+            },
+            SqlAst::Rollup(columns) => SqlAst::Rollup(list(columns)),
+            SqlAst::Cube(columns) => SqlAst::Cube(list(columns)),
+            SqlAst::GroupingSets(sets) => {
+                SqlAst::GroupingSets(sets.iter().map(|set| list(set)).collect())
+            }
+            SqlAst::Window {
+                function,
+                partition_by,
+                order_by,
+                frame,
+            } => SqlAst::Window {
+                function: match function {
+                    WindowFunction::Sum(value) => {
+                        WindowFunction::Sum(node(value))
+                    }
+                    WindowFunction::Lag { value, offset } => {
+                        WindowFunction::Lag {
+                            value: node(value),
+                            offset: *offset,
+                        }
+                    }
+                    WindowFunction::Lead { value, offset } => {
+                        WindowFunction::Lead {
+                            value: node(value),
+                            offset: *offset,
+                        }
+                    }
+                    other => other.clone(),
+                },
+                partition_by: list(partition_by),
+                order_by: list(order_by),
+                frame: frame.clone(),
+            },
+            SqlAst::Alias { value, alias } => SqlAst::Alias {
+                value: node(value),
+                alias: alias.clone(),
+            },
+            SqlAst::Function { name, args } => SqlAst::Function {
+                name: name.clone(),
+                args: list(args),
+            },
+            SqlAst::Binary {
+                left,
+                operator,
+                right,
+            } => SqlAst::Binary {
+                left: node(left),
+                operator: *operator,
+                right: node(right),
+            },
+            other => other.clone(),
+        }
+    }
+
+    /// Direct child nodes, in rendering order.
+    pub fn children(&self) -> Vec<&SqlAst> {
+        match self {
+            SqlAst::Select {
+                columns,
+                from,
+                where_clause,
+                group_by,
+                having,
+                order_by,
+            } => {
+                let mut children: Vec<&SqlAst> = columns.iter().collect();
+                children.push(from);
+                children.extend(where_clause.as_deref());
+                children.extend(group_by.iter().flatten());
+                children.extend(having.as_deref());
+                children.extend(order_by.iter().flatten());
+                children
+            }
+            SqlAst::Subquery(query, _) => vec![query],
+            SqlAst::Join {
+                left, right, on, ..
+            } => vec![left, right, on],
+            SqlAst::Expression(inner) | SqlAst::IsNull { value: inner, .. } => {
+                vec![inner]
+            }
+            SqlAst::Comparison { left, right, .. }
+            | SqlAst::Binary { left, right, .. } => vec![left, right],
+            SqlAst::Logical { items, .. } => items.iter().collect(),
+            SqlAst::OrderBy { expression, .. } => vec![expression],
+            SqlAst::Limit { query, .. } => vec![query],
+            SqlAst::Rollup(columns) | SqlAst::Cube(columns) => {
+                columns.iter().collect()
+            }
+            SqlAst::GroupingSets(sets) => sets.iter().flatten().collect(),
+            SqlAst::Window {
+                function,
+                partition_by,
+                order_by,
+                ..
+            } => {
+                let mut children: Vec<&SqlAst> = match function {
+                    WindowFunction::Sum(value)
+                    | WindowFunction::Lag { value, .. }
+                    | WindowFunction::Lead { value, .. } => vec![value],
+                    _ => vec![],
+                };
+                children.extend(partition_by.iter());
+                children.extend(order_by.iter());
+                children
+            }
+            SqlAst::Alias { value, .. } => vec![value],
+            SqlAst::Function { args, .. } => args.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Parsed expressions in the tree, outer nodes first.
+    pub fn expressions(&self) -> Vec<&Expression> {
+        let mut expressions = match self {
+            SqlAst::Scalar(expression)
+            | SqlAst::ScalarAlias { expression, .. } => vec![expression],
+            _ => vec![],
+        };
+        for child in self.children() {
+            expressions.extend(child.expressions());
+        }
+        expressions
+    }
+
+    /// Rewrites every parsed expression in the tree.
+    pub fn map_expressions(
+        &self,
+        map: &dyn Fn(&Expression) -> Expression,
+    ) -> SqlAst {
+        self.transform(&|ast| match ast {
+            SqlAst::Scalar(expression) => Some(SqlAst::Scalar(map(expression))),
+            SqlAst::ScalarAlias { expression, alias } => {
+                Some(SqlAst::ScalarAlias {
+                    expression: map(expression),
+                    alias: alias.clone(),
+                })
+            }
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Sum(Box<SqlAst>),
+    Lag { value: Box<SqlAst>, offset: u64 },
+    Lead { value: Box<SqlAst>, offset: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameUnits {
+    Rows,
+    Range,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(u64),
+    CurrentRow,
+    Following(u64),
+    UnboundedFollowing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum JoinType {
     Inner,
     Left,
@@ -42,7 +350,18 @@ pub enum JoinType {
     Full,
 }
 
-#[derive(Debug, PartialEq)]
+impl JoinType {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            JoinType::Inner => "INNER JOIN",
+            JoinType::Left => "LEFT JOIN",
+            JoinType::Right => "RIGHT JOIN",
+            JoinType::Full => "FULL JOIN",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operator {
     Equal,
     NotEqual,
@@ -52,12 +371,25 @@ pub enum Operator {
     GreaterOrEqual,
     In,
 }
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LogicalVariant {
     And,
     Or,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NullsOrder {
+    First,
+    Last,
+}
+
+#[derive(Default)]
 pub struct SQLGenerator {
     sql: String,
 }
@@ -79,6 +411,7 @@ impl SQLGenerator {
                 from,
                 where_clause,
                 group_by,
+                having,
                 order_by,
             } => {
                 self.sql.push_str("SELECT");
@@ -93,6 +426,10 @@ impl SQLGenerator {
                     self.sql.push_str(" GROUP BY");
                     self.visit_list(group_by_clause, ",");
                 }
+                if let Some(having_clause) = having {
+                    self.sql.push_str(" HAVING");
+                    self.visit(having_clause);
+                }
                 if let Some(order_by_clause) = order_by {
                     self.sql.push_str(" ORDER BY");
                     self.visit_list(order_by_clause, ",");
@@ -107,6 +444,12 @@ impl SQLGenerator {
             SqlAst::ColumnAlias { column, alias } => {
                 self.sql.push_str(&format!(" {} AS {}", column, alias));
             }
+            SqlAst::Scalar(expression) => {
+                self.sql.push_str(&format!(" {}", expression));
+            }
+            SqlAst::ScalarAlias { expression, alias } => {
+                self.sql.push_str(&format!(" {} AS {}", expression, alias));
+            }
             SqlAst::Join {
                 left,
                 right,
@@ -114,20 +457,16 @@ impl SQLGenerator {
                 on,
             } => {
                 self.visit(left);
-                let join_str = match join_type {
-                    JoinType::Inner => " INNER JOIN",
-                    JoinType::Left => " LEFT JOIN",
-                    JoinType::Right => " RIGHT JOIN",
-                    JoinType::Full => " FULL JOIN",
-                };
-                self.sql.push_str(join_str);
+                self.sql.push_str(&format!(" {}", join_type.keyword()));
                 self.visit(right);
                 self.sql.push_str(" ON");
                 self.visit(on);
             }
             SqlAst::Expression(sql_ast) => {
                 self.sql.push_str(" (");
+                let start = self.sql.len();
                 self.visit(sql_ast);
+                self.trim_leading_space(start);
                 self.sql.push(')');
             }
             SqlAst::Subquery(sql_ast, alias) => {
@@ -139,7 +478,9 @@ impl SQLGenerator {
                 LogicalVariant::And => self.visit_list(items, " AND"),
                 LogicalVariant::Or => {
                     self.sql.push_str(" (");
+                    let start = self.sql.len();
                     self.visit_list(items, " OR");
+                    self.trim_leading_space(start);
                     self.sql.push(')');
                 }
             },
@@ -161,18 +502,212 @@ impl SQLGenerator {
                 self.sql.push_str(operator_str);
                 if let Operator::In = operator {
                     self.sql.push_str(" (");
+                    let start = self.sql.len();
                     self.visit(right);
+                    self.trim_leading_space(start);
                     self.sql.push(')');
                 } else {
                     self.visit(right);
                 }
             }
-            SqlAst::Literal(value) => {
-                self.sql.push_str(&format!(" {}", value));
+            SqlAst::IsNull { value, negated } => {
+                self.visit(value);
+                self.sql.push_str(match negated {
+                    true => " IS NOT NULL",
+                    false => " IS NULL",
+                });
+            }
+            SqlAst::Boolean(value) => {
+                self.sql.push_str(match value {
+                    true => " TRUE",
+                    false => " FALSE",
+                });
+            }
+            SqlAst::Literal(literal) => {
+                let rendered = match literal {
+                    Literal::Integer(value) => value.to_string(),
+                    Literal::Decimal(value) => value.to_string(),
+                    Literal::Text(value) => quoted(value),
+                    Literal::Date(value) => format!("DATE {}", quoted(value)),
+                    Literal::DateTime(value) => {
+                        format!("TIMESTAMP {}", quoted(value))
+                    }
+                    Literal::Timestamp { date, timezone } => format!(
+                        "TIMESTAMP {}",
+                        quoted(&format!("{} 00:00:00 {}", date, timezone))
+                    ),
+                };
+                self.sql.push_str(&format!(" {}", rendered));
+            }
+            SqlAst::OrderBy {
+                expression,
+                direction,
+                nulls,
+            } => {
+                self.visit(expression);
+                self.sql.push_str(match direction {
+                    SortDirection::Asc => " ASC",
+                    SortDirection::Desc => " DESC",
+                });
+                match nulls {
+                    Some(NullsOrder::First) => {
+                        self.sql.push_str(" NULLS FIRST")
+                    }
+                    Some(NullsOrder::Last) => self.sql.push_str(" NULLS LAST"),
+                    None => {}
+                }
+            }
+            SqlAst::Limit {
+                query,
+                limit,
+                offset,
+            } => {
+                self.visit(query);
+                if let Some(limit) = limit {
+                    self.sql.push_str(&format!(" LIMIT {}", limit));
+                }
+                if let Some(offset) = offset {
+                    self.sql.push_str(&format!(" OFFSET {}", offset));
+                }
+            }
+            SqlAst::Window {
+                function,
+                partition_by,
+                order_by,
+                frame,
+            } => {
+                match function {
+                    WindowFunction::RowNumber => {
+                        self.sql.push_str(" row_number()")
+                    }
+                    WindowFunction::Rank => self.sql.push_str(" rank()"),
+                    WindowFunction::DenseRank => {
+                        self.sql.push_str(" dense_rank()")
+                    }
+                    WindowFunction::Sum(value) => {
+                        self.sql.push_str(" sum(");
+                        let start = self.sql.len();
+                        self.visit(value);
+                        self.trim_leading_space(start);
+                        self.sql.push(')');
+                    }
+                    WindowFunction::Lag { value, offset } => {
+                        self.sql.push_str(" lag(");
+                        let start = self.sql.len();
+                        self.visit(value);
+                        self.trim_leading_space(start);
+                        self.sql.push_str(&format!(", {})", offset));
+                    }
+                    WindowFunction::Lead { value, offset } => {
+                        self.sql.push_str(" lead(");
+                        let start = self.sql.len();
+                        self.visit(value);
+                        self.trim_leading_space(start);
+                        self.sql.push_str(&format!(", {})", offset));
+                    }
+                }
+                self.sql.push_str(" OVER (");
+                let start = self.sql.len();
+                if !partition_by.is_empty() {
+                    self.sql.push_str(" PARTITION BY");
+                    self.visit_list(partition_by, ",");
+                }
+                if !order_by.is_empty() {
+                    self.sql.push_str(" ORDER BY");
+                    self.visit_list(order_by, ",");
+                }
+                if let Some(frame) = frame {
+                    self.sql.push_str(match frame.units {
+                        FrameUnits::Rows => " ROWS",
+                        FrameUnits::Range => " RANGE",
+                    });
+                    self.sql.push_str(&format!(
+                        " BETWEEN {} AND {}",
+                        frame_bound(frame.start),
+                        frame_bound(frame.end)
+                    ));
+                }
+                self.trim_leading_space(start);
+                self.sql.push(')');
+            }
+            SqlAst::Alias { value, alias } => {
+                self.visit(value);
+                self.sql.push_str(&format!(" AS {}", alias));
+            }
+            SqlAst::Function { name, args } => {
+                self.sql.push_str(&format!(" {}(", name));
+                let start = self.sql.len();
+                self.visit_list(args, ",");
+                self.trim_leading_space(start);
+                self.sql.push(')');
+            }
+            SqlAst::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                // Left associative, like the parsed expressions.
+                self.visit_operand(
+                    left,
+                    precedence(left) < operator.precedence(),
+                );
+                self.sql.push_str(&format!(" {}", operator.symbol()));
+                self.visit_operand(
+                    right,
+                    precedence(right) <= operator.precedence(),
+                );
+            }
+            SqlAst::Rollup(columns) => {
+                self.sql.push_str(" ROLLUP");
+                self.visit_group(columns);
+            }
+            SqlAst::Cube(columns) => {
+                self.sql.push_str(" CUBE");
+                self.visit_group(columns);
+            }
+            SqlAst::GroupingSets(sets) => {
+                self.sql.push_str(" GROUPING SETS (");
+                let start = self.sql.len();
+                for (index, set) in sets.iter().enumerate() {
+                    if index > 0 {
+                        self.sql.push(',');
+                    }
+                    self.visit_group(set);
+                }
+                self.trim_leading_space(start);
+                self.sql.push(')');
             }
         }
     }
 
+    /// Every node is rendered with a leading space, which reads oddly right
+    /// after an opening parenthesis.
+    fn trim_leading_space(&mut self, start: usize) {
+        if self.sql[start..].starts_with(' ') {
+            self.sql.remove(start);
+        }
+    }
+
+    /// Parenthesized, comma separated list of columns.
+    fn visit_group(&mut self, columns: &[SqlAst]) {
+        self.sql.push_str(" (");
+        let start = self.sql.len();
+        self.visit_list(columns, ",");
+        self.trim_leading_space(start);
+        self.sql.push(')');
+    }
+
+    fn visit_operand(&mut self, operand: &SqlAst, parenthesized: bool) {
+        if !parenthesized {
+            return self.visit(operand);
+        }
+        self.sql.push_str(" (");
+        let start = self.sql.len();
+        self.visit(operand);
+        self.trim_leading_space(start);
+        self.sql.push(')');
+    }
+
     fn visit_list(&mut self, items: &[SqlAst], separator: &str) {
         for (index, item) in items.iter().enumerate() {
             if index > 0 {
@@ -183,27 +718,49 @@ impl SQLGenerator {
     }
 }
 
+fn precedence(ast: &SqlAst) -> u8 {
+    match ast {
+        SqlAst::Binary { operator, .. } => operator.precedence(),
+        SqlAst::Scalar(expression) => expression.precedence(),
+        _ => u8::MAX,
+    }
+}
+
+fn frame_bound(bound: FrameBound) -> String {
+    match bound {
+        FrameBound::UnboundedPreceding => "UNBOUNDED PRECEDING".to_string(),
+        FrameBound::Preceding(rows) => format!("{} PRECEDING", rows),
+        FrameBound::CurrentRow => "CURRENT ROW".to_string(),
+        FrameBound::Following(rows) => format!("{} FOLLOWING", rows),
+        FrameBound::UnboundedFollowing => "UNBOUNDED FOLLOWING".to_string(),
+    }
+}
+
+fn quoted(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::rc;
+    use crate::arc;
 
     #[test]
     fn test_sql_ast() {
-        let column = SqlAst::Column(rc!["username"]);
+        let column = SqlAst::Column(arc!["username"]);
         let column_alias = SqlAst::ColumnAlias {
-            column: rc!["username"],
-            alias: rc!["user"],
+            column: arc!["username"],
+            alias: arc!["user"],
         };
 
         let join_clause = SqlAst::Join {
-            left: Box::new(SqlAst::Table(rc!["orders"], rc!["orders"])),
-            right: Box::new(SqlAst::Table(rc!["users"], rc!["users"])),
+            left: Box::new(SqlAst::Table(arc!["orders"], arc!["orders"])),
+            right: Box::new(SqlAst::Table(arc!["users"], arc!["users"])),
             join_type: JoinType::Inner,
             on: Box::new(SqlAst::Comparison {
-                left: Box::new(SqlAst::Column(rc!["orders.user_id"])),
+                left: Box::new(SqlAst::Column(arc!["orders.user_id"])),
                 operator: Operator::Equal,
-                right: Box::new(SqlAst::Column(rc!["users.id"])),
+                right: Box::new(SqlAst::Column(arc!["users.id"])),
             }),
         };
 
@@ -214,8 +771,8 @@ mod tests {
         }
 
         if let SqlAst::ColumnAlias { column, alias } = column_alias {
-            assert_eq!(column, rc!["username"]);
-            assert_eq!(alias, rc!["user"]);
+            assert_eq!(column, arc!["username"]);
+            assert_eq!(alias, arc!["user"]);
         } else {
             panic!("Expected SQLAst::ColumnAlias");
         }
@@ -228,14 +785,14 @@ mod tests {
         } = join_clause
         {
             if let SqlAst::Table(left_name, alias) = *left {
-                assert_eq!(left_name, rc!["orders"]);
-                assert_eq!(alias, rc!["orders"]);
+                assert_eq!(left_name, arc!["orders"]);
+                assert_eq!(alias, arc!["orders"]);
             } else {
                 panic!("Expected SQLAst::Table");
             }
             if let SqlAst::Table(right_name, alias) = *right {
-                assert_eq!(right_name, rc!["users"]);
-                assert_eq!(alias, rc!["users"]);
+                assert_eq!(right_name, arc!["users"]);
+                assert_eq!(alias, arc!["users"]);
             } else {
                 panic!("Expected SQLAst::Table");
             }
@@ -249,12 +806,12 @@ mod tests {
             {
                 assert_eq!(operator, Operator::Equal);
                 if let SqlAst::Column(on_left_name) = *on_left {
-                    assert_eq!(on_left_name, rc!["orders.user_id"]);
+                    assert_eq!(on_left_name, arc!["orders.user_id"]);
                 } else {
                     panic!("Expected SQLAst::Column");
                 }
                 if let SqlAst::Column(on_right_name) = *on_right {
-                    assert_eq!(on_right_name, rc!["users.id"]);
+                    assert_eq!(on_right_name, arc!["users.id"]);
                 } else {
                     panic!("Expected SQLAst::Column");
                 }
@@ -271,19 +828,20 @@ mod tests {
         let final_query = SqlAst::Select {
             columns: vec![
                 SqlAst::ColumnAlias {
-                    column: rc!["username"],
-                    alias: rc!["user"],
+                    column: arc!["username"],
+                    alias: arc!["user"],
                 },
-                SqlAst::Column(rc!["email"]),
+                SqlAst::Column(arc!["email"]),
             ],
-            from: Box::new(SqlAst::Table(rc!["users"], rc!["users"])),
+            from: Box::new(SqlAst::Table(arc!["users"], arc!["users"])),
             where_clause: Some(Box::new(SqlAst::Comparison {
-                left: Box::new(SqlAst::Column(rc!["age"])),
+                left: Box::new(SqlAst::Column(arc!["age"])),
                 operator: Operator::GreaterOrEqual,
-                right: Box::new(SqlAst::Column(rc!["18"])),
+                right: Box::new(SqlAst::Column(arc!["18"])),
             })),
             group_by: None,
-            order_by: Some(vec![SqlAst::Column(rc!["username"])]),
+            having: None,
+            order_by: Some(vec![SqlAst::Column(arc!["username"])]),
         };
 
         let mut generator = SQLGenerator::new();
@@ -298,63 +856,65 @@ mod tests {
     #[test]
     fn test_generate_sql_with_subquery_and_joins() {
         let subquery_ast = SqlAst::Select {
-            columns: vec![SqlAst::Column(rc!["inner_col"])],
+            columns: vec![SqlAst::Column(arc!["inner_col"])],
             from: Box::new(SqlAst::Table(
-                rc!["inner_table"],
-                rc!["inner_table"],
+                arc!["inner_table"],
+                arc!["inner_table"],
             )),
             where_clause: None,
             group_by: None,
+            having: None,
             order_by: None,
         };
 
         let inner_join = SqlAst::Join {
-            left: Box::new(SqlAst::Table(rc!["table1"], rc!["table1"])),
+            left: Box::new(SqlAst::Table(arc!["table1"], arc!["table1"])),
             right: Box::new(SqlAst::Expression(Box::new(subquery_ast))),
             join_type: JoinType::Inner,
             on: Box::new(SqlAst::Comparison {
-                left: Box::new(SqlAst::Column(rc!["table1.id"])),
+                left: Box::new(SqlAst::Column(arc!["table1.id"])),
                 operator: Operator::Equal,
-                right: Box::new(SqlAst::Column(rc!["inner_table.fk_id"])),
+                right: Box::new(SqlAst::Column(arc!["inner_table.fk_id"])),
             }),
         };
 
         let left_join = SqlAst::Join {
             left: Box::new(inner_join),
-            right: Box::new(SqlAst::Table(rc!["table2"], rc!["table2"])),
+            right: Box::new(SqlAst::Table(arc!["table2"], arc!["table2"])),
             join_type: JoinType::Left,
             on: Box::new(SqlAst::Comparison {
-                left: Box::new(SqlAst::Column(rc!["table1.id"])),
+                left: Box::new(SqlAst::Column(arc!["table1.id"])),
                 operator: Operator::Equal,
-                right: Box::new(SqlAst::Column(rc!["table2.fk_id"])),
+                right: Box::new(SqlAst::Column(arc!["table2.fk_id"])),
             }),
         };
 
         let final_query = SqlAst::Select {
             columns: vec![
                 SqlAst::ColumnAlias {
-                    column: rc!["table1.col1"],
-                    alias: rc!["alias1"],
+                    column: arc!["table1.col1"],
+                    alias: arc!["alias1"],
                 },
-                SqlAst::Column(rc!["table2.col2"]),
+                SqlAst::Column(arc!["table2.col2"]),
             ],
             from: Box::new(left_join),
             where_clause: Some(Box::new(SqlAst::Logical {
                 items: vec![
                     SqlAst::Comparison {
-                        left: Box::new(SqlAst::Column(rc!["date"])),
+                        left: Box::new(SqlAst::Column(arc!["date"])),
                         operator: Operator::GreaterOrEqual,
-                        right: Box::new(SqlAst::Column(rc!["?"])),
+                        right: Box::new(SqlAst::Column(arc!["?"])),
                     },
                     SqlAst::Comparison {
-                        left: Box::new(SqlAst::Column(rc!["date"])),
+                        left: Box::new(SqlAst::Column(arc!["date"])),
                         operator: Operator::Less,
-                        right: Box::new(SqlAst::Column(rc!["?"])),
+                        right: Box::new(SqlAst::Column(arc!["?"])),
                     },
                 ],
                 variant: LogicalVariant::And,
             })),
             group_by: None,
+            having: None,
             order_by: None,
         };
 
@@ -372,42 +932,42 @@ mod tests {
         let aggregation_query = SqlAst::Select {
             columns: vec![
                 SqlAst::ColumnAlias {
-                    column: rc!["from_unixtime(fact_table.ts, 'YYYY-mm-dd')"],
-                    alias: rc!["date"],
+                    column: arc!["from_unixtime(fact_table.ts, 'YYYY-mm-dd')"],
+                    alias: arc!["date"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["campaign_hierarchy.campaign_id"],
-                    alias: rc!["campaign_id"],
+                    column: arc!["campaign_hierarchy.campaign_id"],
+                    alias: arc!["campaign_id"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["fact_table.line_item_id"],
-                    alias: rc!["line_item_id"],
+                    column: arc!["fact_table.line_item_id"],
+                    alias: arc!["line_item_id"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["sum(fact_table.impressions)"],
-                    alias: rc!["sum_impressions"],
+                    column: arc!["sum(fact_table.impressions)"],
+                    alias: arc!["sum_impressions"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["sum(fact_table.clicks)"],
-                    alias: rc!["sum_clicks"],
+                    column: arc!["sum(fact_table.clicks)"],
+                    alias: arc!["sum_clicks"],
                 },
             ],
             from: Box::new(SqlAst::Join {
                 left: Box::new(SqlAst::Table(
-                    rc!["fact_table"],
-                    rc!["fact_table"],
+                    arc!["fact_table"],
+                    arc!["fact_table"],
                 )),
                 right: Box::new(SqlAst::Table(
-                    rc!["campaign_hierarchy"],
-                    rc!["campaign_hierarchy"],
+                    arc!["campaign_hierarchy"],
+                    arc!["campaign_hierarchy"],
                 )),
                 join_type: JoinType::Left,
                 on: Box::new(SqlAst::Comparison {
-                    left: Box::new(SqlAst::Column(rc![
+                    left: Box::new(SqlAst::Column(arc![
                         "fact_table.line_item_id"
                     ])),
                     operator: Operator::Equal,
-                    right: Box::new(SqlAst::Column(rc![
+                    right: Box::new(SqlAst::Column(arc![
                         "campaign_hierarchy.line_item_id"
                     ])),
                 }),
@@ -415,46 +975,47 @@ mod tests {
             where_clause: Some(Box::new(SqlAst::Logical {
                 items: vec![
                     SqlAst::Comparison {
-                        left: Box::new(SqlAst::Column(rc![
+                        left: Box::new(SqlAst::Column(arc![
                             "from_unixtime(fact_table.ts, 'YYYY-mm-dd')"
                         ])),
                         operator: Operator::GreaterOrEqual,
-                        right: Box::new(SqlAst::Column(rc!["?"])),
+                        right: Box::new(SqlAst::Column(arc!["?"])),
                     },
                     SqlAst::Comparison {
-                        left: Box::new(SqlAst::Column(rc![
+                        left: Box::new(SqlAst::Column(arc![
                             "from_unixtime(fact_table.ts, 'YYYY-mm-dd')"
                         ])),
                         operator: Operator::Less,
-                        right: Box::new(SqlAst::Column(rc!["?"])),
+                        right: Box::new(SqlAst::Column(arc!["?"])),
                     },
                 ],
                 variant: LogicalVariant::And,
             })),
             group_by: Some(vec![
-                SqlAst::Column(rc![
+                SqlAst::Column(arc![
                     "from_unixtime(fact_table.ts, 'YYYY-mm-dd')"
                 ]),
-                SqlAst::Column(rc!["fact_table.line_item_id"]),
-                SqlAst::Column(rc!["campaign_hierarchy.campaign_id"]),
+                SqlAst::Column(arc!["fact_table.line_item_id"]),
+                SqlAst::Column(arc!["campaign_hierarchy.campaign_id"]),
             ]),
+            having: None,
             order_by: None,
         };
 
         let dim_join = SqlAst::Join {
             left: Box::new(SqlAst::Subquery(
                 Box::new(aggregation_query),
-                rc!["facts"],
+                arc!["facts"],
             )),
             right: Box::new(SqlAst::Table(
-                rc!["dim_campaign"],
-                rc!["dim_campaign"],
+                arc!["dim_campaign"],
+                arc!["dim_campaign"],
             )),
             join_type: JoinType::Left,
             on: Box::new(SqlAst::Comparison {
-                left: Box::new(SqlAst::Column(rc!["facts.campaign_id"])),
+                left: Box::new(SqlAst::Column(arc!["facts.campaign_id"])),
                 operator: Operator::Equal,
-                right: Box::new(SqlAst::Column(rc![
+                right: Box::new(SqlAst::Column(arc![
                     "dim_campaign.campaign_id"
                 ])),
             }),
@@ -463,37 +1024,38 @@ mod tests {
         let final_query = SqlAst::Select {
             columns: vec![
                 SqlAst::ColumnAlias {
-                    column: rc!["facts.date"],
-                    alias: rc!["date"],
+                    column: arc!["facts.date"],
+                    alias: arc!["date"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["facts.campaign_id"],
-                    alias: rc!["campaign_id"],
+                    column: arc!["facts.campaign_id"],
+                    alias: arc!["campaign_id"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["dim_campaign.campaign_name"],
-                    alias: rc!["campaign_name"],
+                    column: arc!["dim_campaign.campaign_name"],
+                    alias: arc!["campaign_name"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["facts.line_item_id"],
-                    alias: rc!["line_item_id"],
+                    column: arc!["facts.line_item_id"],
+                    alias: arc!["line_item_id"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["dim_campaign.line_item_name"],
-                    alias: rc!["line_item_name"],
+                    column: arc!["dim_campaign.line_item_name"],
+                    alias: arc!["line_item_name"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["facts.sum_impressions"],
-                    alias: rc!["sum_impressions"],
+                    column: arc!["facts.sum_impressions"],
+                    alias: arc!["sum_impressions"],
                 },
                 SqlAst::ColumnAlias {
-                    column: rc!["facts.sum_clicks"],
-                    alias: rc!["sum_clicks"],
+                    column: arc!["facts.sum_clicks"],
+                    alias: arc!["sum_clicks"],
                 },
             ],
             from: Box::new(dim_join),
             where_clause: None,
             group_by: None,
+            having: None,
             order_by: None,
         };
 
@@ -505,4 +1067,356 @@ mod tests {
             "SELECT facts.date AS date, facts.campaign_id AS campaign_id, dim_campaign.campaign_name AS campaign_name, facts.line_item_id AS line_item_id, dim_campaign.line_item_name AS line_item_name, facts.sum_impressions AS sum_impressions, facts.sum_clicks AS sum_clicks FROM (SELECT from_unixtime(fact_table.ts, 'YYYY-mm-dd') AS date, campaign_hierarchy.campaign_id AS campaign_id, fact_table.line_item_id AS line_item_id, sum(fact_table.impressions) AS sum_impressions, sum(fact_table.clicks) AS sum_clicks FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= ? AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < ? GROUP BY from_unixtime(fact_table.ts, 'YYYY-mm-dd'), fact_table.line_item_id, campaign_hierarchy.campaign_id) facts LEFT JOIN dim_campaign dim_campaign ON facts.campaign_id = dim_campaign.campaign_id"
         );
     }
+
+    #[test]
+    fn test_generate_sql_order_by() {
+        let query = SqlAst::Select {
+            columns: vec![SqlAst::Column(arc!["date"])],
+            from: Box::new(SqlAst::Table(arc!["facts"], arc!["facts"])),
+            where_clause: None,
+            group_by: None,
+            having: None,
+            order_by: Some(vec![
+                SqlAst::OrderBy {
+                    expression: Box::new(SqlAst::Column(arc!["date"])),
+                    direction: SortDirection::Asc,
+                    nulls: None,
+                },
+                SqlAst::OrderBy {
+                    expression: Box::new(SqlAst::Column(arc!["cpm"])),
+                    direction: SortDirection::Desc,
+                    nulls: Some(NullsOrder::Last),
+                },
+            ]),
+        };
+
+        let sql = SQLGenerator::new().generate_sql(&query);
+        assert_eq!(
+            sql.trim(),
+            "SELECT date FROM facts facts ORDER BY date ASC, cpm DESC NULLS LAST"
+        );
+    }
+
+    #[test]
+    fn test_generate_sql_limit() {
+        let query = SqlAst::Limit {
+            query: Box::new(SqlAst::Select {
+                columns: vec![SqlAst::Column(arc!["date"])],
+                from: Box::new(SqlAst::Table(arc!["facts"], arc!["facts"])),
+                where_clause: None,
+                group_by: None,
+                having: None,
+                order_by: None,
+            }),
+            limit: Some(100),
+            offset: Some(200),
+        };
+
+        let sql = SQLGenerator::new().generate_sql(&query);
+        assert_eq!(
+            sql.trim(),
+            "SELECT date FROM facts facts LIMIT 100 OFFSET 200"
+        );
+    }
+
+    #[test]
+    fn test_generate_sql_having() {
+        let query = SqlAst::Select {
+            columns: vec![
+                SqlAst::Column(arc!["campaign_id"]),
+                SqlAst::ColumnAlias {
+                    column: arc!["sum(impressions)"],
+                    alias: arc!["sum_impressions"],
+                },
+            ],
+            from: Box::new(SqlAst::Table(arc!["facts"], arc!["facts"])),
+            where_clause: None,
+            group_by: Some(vec![SqlAst::Column(arc!["campaign_id"])]),
+            having: Some(Box::new(SqlAst::Comparison {
+                left: Box::new(SqlAst::Column(arc!["sum(impressions)"])),
+                operator: Operator::Greater,
+                right: Box::new(SqlAst::Literal(Literal::Integer(1000))),
+            })),
+            order_by: None,
+        };
+
+        let sql = SQLGenerator::new().generate_sql(&query);
+        assert_eq!(
+            sql.trim(),
+            "SELECT campaign_id, sum(impressions) AS sum_impressions FROM facts facts GROUP BY campaign_id HAVING sum(impressions) > 1000"
+        );
+    }
+
+    #[test]
+    fn test_generate_sql_literals() {
+        let comparison = |column: &str, literal: Literal| SqlAst::Comparison {
+            left: Box::new(SqlAst::Column(Arc::from(column))),
+            operator: Operator::Equal,
+            right: Box::new(SqlAst::Literal(literal)),
+        };
+        let predicate = SqlAst::Logical {
+            items: vec![
+                comparison("id", Literal::Integer(-7)),
+                comparison("spend", Literal::Decimal(arc!["2.50"])),
+                comparison("name", Literal::Text(arc!["it's"])),
+                comparison("date", Literal::Date(arc!["2020-01-01"])),
+                comparison(
+                    "ts",
+                    Literal::Timestamp {
+                        date: arc!["2020-01-01"],
+                        timezone: arc!["Europe/Berlin"],
+                    },
+                ),
+                comparison(
+                    "hour",
+                    Literal::DateTime(arc!["2020-01-01 13:00:00"]),
+                ),
+                SqlAst::IsNull {
+                    value: Box::new(SqlAst::Column(arc!["campaign_id"])),
+                    negated: true,
+                },
+            ],
+            variant: LogicalVariant::And,
+        };
+
+        let sql = SQLGenerator::new().generate_sql(&predicate);
+        assert_eq!(
+            sql.trim(),
+            "id = -7 AND spend = 2.50 AND name = 'it''s' AND date = DATE '2020-01-01' AND ts = TIMESTAMP '2020-01-01 00:00:00 Europe/Berlin' AND hour = TIMESTAMP '2020-01-01 13:00:00' AND campaign_id IS NOT NULL"
+        );
+    }
+
+    #[test]
+    fn test_generate_sql_grouping_sets() {
+        let columns = || {
+            vec![
+                SqlAst::Column(arc!["campaign_id"]),
+                SqlAst::Column(arc!["line_item_id"]),
+            ]
+        };
+        let query = |group_by: SqlAst| SqlAst::Select {
+            columns: columns(),
+            from: Box::new(SqlAst::Table(arc!["facts"], arc!["facts"])),
+            where_clause: None,
+            group_by: Some(vec![group_by]),
+            having: None,
+            order_by: None,
+        };
+        let base = "SELECT campaign_id, line_item_id FROM facts facts GROUP BY";
+
+        let sql =
+            SQLGenerator::new().generate_sql(&query(SqlAst::Rollup(columns())));
+        assert_eq!(sql, format!("{} ROLLUP (campaign_id, line_item_id)", base));
+
+        let sql =
+            SQLGenerator::new().generate_sql(&query(SqlAst::Cube(columns())));
+        assert_eq!(sql, format!("{} CUBE (campaign_id, line_item_id)", base));
+
+        let sql = SQLGenerator::new().generate_sql(&query(
+            SqlAst::GroupingSets(vec![
+                columns(),
+                vec![SqlAst::Column(arc!["campaign_id"])],
+                vec![],
+            ]),
+        ));
+        assert_eq!(
+            sql,
+            format!(
+                "{} GROUPING SETS ((campaign_id, line_item_id), (campaign_id), ())",
+                base
+            )
+        );
+    }
+
+    #[test]
+    fn test_generate_sql_window() {
+        let order_by = || {
+            vec![SqlAst::OrderBy {
+                expression: Box::new(SqlAst::Column(arc!["date"])),
+                direction: SortDirection::Asc,
+                nulls: None,
+            }]
+        };
+        let window =
+            |function: WindowFunction, frame: Option<Frame>| SqlAst::Alias {
+                value: Box::new(SqlAst::Window {
+                    function,
+                    partition_by: vec![SqlAst::Column(arc!["campaign_id"])],
+                    order_by: order_by(),
+                    frame,
+                }),
+                alias: arc!["w"],
+            };
+        let impressions = || Box::new(SqlAst::Column(arc!["impressions"]));
+
+        for (ast, expected) in [
+            (
+                window(WindowFunction::RowNumber, None),
+                "row_number() OVER (PARTITION BY campaign_id ORDER BY date ASC) AS w",
+            ),
+            (
+                window(
+                    WindowFunction::Sum(impressions()),
+                    Some(Frame {
+                        units: FrameUnits::Rows,
+                        start: FrameBound::UnboundedPreceding,
+                        end: FrameBound::CurrentRow,
+                    }),
+                ),
+                "sum(impressions) OVER (PARTITION BY campaign_id ORDER BY date ASC ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW) AS w",
+            ),
+            (
+                window(
+                    WindowFunction::Lag {
+                        value: impressions(),
+                        offset: 1,
+                    },
+                    Some(Frame {
+                        units: FrameUnits::Range,
+                        start: FrameBound::Preceding(2),
+                        end: FrameBound::Following(3),
+                    }),
+                ),
+                "lag(impressions, 1) OVER (PARTITION BY campaign_id ORDER BY date ASC RANGE BETWEEN 2 PRECEDING AND 3 FOLLOWING) AS w",
+            ),
+            (
+                SqlAst::Window {
+                    function: WindowFunction::Rank,
+                    partition_by: vec![],
+                    order_by: order_by(),
+                    frame: None,
+                },
+                "rank() OVER (ORDER BY date ASC)",
+            ),
+        ] {
+            let sql = SQLGenerator::new().generate_sql(&ast);
+            assert_eq!(sql.trim(), expected);
+        }
+    }
+
+    #[test]
+    fn test_generate_sql_binary() {
+        let spend = || Expression::Identifier(arc!["spend"]);
+        let total = SqlAst::Function {
+            name: arc!["nullif"],
+            args: vec![
+                SqlAst::Window {
+                    function: WindowFunction::Sum(Box::new(SqlAst::Scalar(
+                        spend(),
+                    ))),
+                    partition_by: vec![],
+                    order_by: vec![],
+                    frame: None,
+                },
+                SqlAst::Literal(Literal::Integer(0)),
+            ],
+        };
+        let share = SqlAst::Binary {
+            left: Box::new(SqlAst::Scalar(Expression::Binary {
+                left: Box::new(Expression::Number(arc!["100.0"])),
+                operator: BinaryOperator::Multiply,
+                right: Box::new(spend()),
+            })),
+            operator: BinaryOperator::Divide,
+            right: Box::new(total),
+        };
+        let sql = SQLGenerator::new().generate_sql(&share);
+        assert_eq!(sql.trim(), "100.0 * spend / nullif(sum(spend) OVER (), 0)");
+
+        let difference = SqlAst::Binary {
+            left: Box::new(SqlAst::Scalar(spend())),
+            operator: BinaryOperator::Subtract,
+            right: Box::new(share),
+        };
+        let sql = SQLGenerator::new().generate_sql(&difference);
+        assert_eq!(
+            sql.trim(),
+            "spend - 100.0 * spend / nullif(sum(spend) OVER (), 0)"
+        );
+
+        let scaled = SqlAst::Binary {
+            left: Box::new(difference),
+            operator: BinaryOperator::Multiply,
+            right: Box::new(SqlAst::Scalar(Expression::Number(arc!["2"]))),
+        };
+        let sql = SQLGenerator::new().generate_sql(&scaled);
+        assert_eq!(
+            sql.trim(),
+            "(spend - 100.0 * spend / nullif(sum(spend) OVER (), 0)) * 2"
+        );
+    }
+
+    #[test]
+    fn test_map_expressions() {
+        let identifier = |name: &str| Expression::Identifier(name.into());
+        let ast = SqlAst::Select {
+            columns: vec![SqlAst::ScalarAlias {
+                expression: identifier("spend"),
+                alias: arc!["spend"],
+            }],
+            from: Box::new(SqlAst::Table(arc!["facts"], arc!["facts"])),
+            where_clause: Some(Box::new(SqlAst::Comparison {
+                left: Box::new(SqlAst::Scalar(identifier("campaign_id"))),
+                operator: Operator::Equal,
+                right: Box::new(SqlAst::Literal(Literal::Integer(7))),
+            })),
+            group_by: Some(vec![SqlAst::Rollup(vec![SqlAst::Scalar(
+                identifier("campaign_id"),
+            )])]),
+            having: None,
+            order_by: None,
+        };
+        let mapped = ast.map_expressions(&|e| {
+            e.replace_identifiers(&|name| Expression::Column {
+                table: "t".into(),
+                column: name.into(),
+            })
+        });
+        let sql = SQLGenerator::new().generate_sql(&mapped);
+        assert_eq!(
+            sql.trim(),
+            "SELECT t.spend AS spend FROM facts facts WHERE t.campaign_id = 7 GROUP BY ROLLUP (t.campaign_id)"
+        );
+    }
+
+    #[test]
+    fn test_expressions() {
+        let identifier = |name: &str| Expression::Identifier(name.into());
+        let ast = SqlAst::Limit {
+            query: Box::new(SqlAst::Select {
+                columns: vec![SqlAst::ScalarAlias {
+                    expression: identifier("a"),
+                    alias: arc!["a"],
+                }],
+                from: Box::new(SqlAst::Table(arc!["t"], arc!["t"])),
+                where_clause: Some(Box::new(SqlAst::Comparison {
+                    left: Box::new(SqlAst::Scalar(identifier("b"))),
+                    operator: Operator::Equal,
+                    right: Box::new(SqlAst::Literal(Literal::Integer(1))),
+                })),
+                group_by: Some(vec![SqlAst::GroupingSets(vec![
+                    vec![SqlAst::Scalar(identifier("c"))],
+                    vec![],
+                ])]),
+                having: None,
+                order_by: Some(vec![SqlAst::OrderBy {
+                    expression: Box::new(SqlAst::Window {
+                        function: WindowFunction::Sum(Box::new(
+                            SqlAst::Scalar(identifier("d")),
+                        )),
+                        partition_by: vec![SqlAst::Scalar(identifier("e"))],
+                        order_by: vec![],
+                        frame: None,
+                    }),
+                    direction: SortDirection::Asc,
+                    nulls: None,
+                }]),
+            }),
+            limit: Some(10),
+            offset: None,
+        };
+        let names: Vec<String> =
+            ast.expressions().iter().map(|e| e.to_string()).collect();
+        assert_eq!(names, vec!["a", "b", "c", "d", "e"]);
+    }
 }
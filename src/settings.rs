@@ -1,5 +1,6 @@
 use config::{Config, ConfigError, File};
 use serde::Deserialize;
+use std::path::PathBuf;
 
 #[derive(Debug, Deserialize)]
 #[allow(unused)]
@@ -7,12 +8,33 @@ pub struct Database {
     pub url: String,
 }
 
+/// Locations of the schema and datasource definitions, the repository
+/// defaults when unset.
+#[derive(Debug, Default, Deserialize)]
+#[allow(unused)]
+pub struct Paths {
+    pub schema: Option<PathBuf>,
+    pub datasources: Option<PathBuf>,
+}
+
+/// Authentication of the callers the gateway in front of the API names.
+#[derive(Debug, Default, Deserialize)]
+#[allow(unused)]
+pub struct Auth {
+    /// Key the gateway signs the principal headers with, the headers being
+    /// refused when unset.
+    pub principal_secret: Option<String>,
+}
 
 #[derive(Debug, Deserialize)]
 #[allow(unused)]
 pub struct Settings {
     pub debug: bool,
     pub database: Database,
+    #[serde(default)]
+    pub paths: Paths,
+    #[serde(default)]
+    pub auth: Auth,
 }
 
 impl Settings {
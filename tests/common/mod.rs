@@ -3,6 +3,7 @@ use serde::de::DeserializeOwned;
 use std::fs;
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum TestError {
     LoadFile(String, String),
     ParseJson(String, String),
@@ -1,9 +1,10 @@
 use crate::common::{load_json, load_yaml};
 use reporting::domain::models::{
-    Column, ColumnType, Datasource, ReportRequest, ReportStatus,
+    Column, ColumnType, DataType, Datasource, ReportRequest, ReportStatus,
 };
+use reporting::domain::registry::DatasourceRegistry;
 use reporting::domain::service::ReportService;
-use std::rc::Rc;
+use std::sync::Arc;
 
 mod common;
 #[test]
@@ -13,36 +14,55 @@ fn test_report_conversion() {
     let request: ReportRequest =
         load_json(request_file).expect("Could not parse request json");
 
-    let datasource_file = "test/datasource.yaml";
+    let datasource_file = "test/datasources/default.yaml";
     let datasource: Datasource =
         load_yaml(datasource_file).expect("Could not parse request yaml");
 
-    let report_service = ReportService::new(datasource);
-    let report = report_service.create_report(request);
+    let registry = DatasourceRegistry::new(vec![datasource])
+        .expect("Datasource should be valid");
+    let report_service = ReportService::new(registry);
+    let report = report_service
+        .create_report(request)
+        .expect("Report should be created");
 
     assert_eq!(report.status, ReportStatus::Pending);
 }
 #[test]
 fn integration_test_generated_query() {
     use reporting::executor::planner::QueryPlanner;
-    use reporting::domain::models::{Datasource, Column, ReportRequest, Filter, ColumnType};
+    use reporting::domain::models::{Datasource, Column, DataType, ReportRequest, Filter, ColumnType, Schema};
     use reporting::executor::query::SQLGenerator;
-    use reporting::rc;
+    use reporting::arc;
 
     let column = Column {
-        name: rc!["username"],
-        column_id: rc!["username"],
-        expression: rc!["username"],
+        name: arc!["username"],
+        column_id: arc!["username"],
+        expression: arc!["username"],
         column_type: ColumnType::Grouping,
-        data_type: rc!["text"],
+        data_type: DataType::Text,
+        compatible_groupings: None,
+    };
+    let date = Column {
+        name: arc!["date"],
+        column_id: arc!["date"],
+        expression: arc!["from_unixtime(fact_table.ts, 'YYYY-mm-dd')"],
+        column_type: ColumnType::Grouping,
+        data_type: DataType::Date,
+        compatible_groupings: None,
     };
 
     let datasource = Datasource {
-        name: rc!["default"],
-        columns: vec![column],
+        name: arc!["default"],
+        columns: vec![column, date],
+        timezone: None,
+        policies: vec![],
     };
 
-    let planner = QueryPlanner::new(datasource);
+    let schema: Schema =
+        load_yaml("test/schema.yaml").expect("Could not parse schema yaml");
+
+    let planner = QueryPlanner::new(datasource, schema)
+        .expect("Datasource should be valid");
     let request = ReportRequest {
         columns: vec!["username".to_string()],
         filters: Filter::And { value: vec![
@@ -50,6 +70,16 @@ fn integration_test_generated_query() {
             Filter::Lt { column: "date".to_string(), value: "2021-01-01".to_string() },
         ]},
         sort: vec![],
+        limit: None,
+        offset: None,
+        cursor: None,
+        granularity: None,
+        timezone: None,
+        compare: None,
+        totals: None,
+        top_n: None,
+        pivot: None,
+        datasource: None,
     };
 
     let ast = planner.plan(request).expect("Planning should succeed");
@@ -57,18 +87,19 @@ fn integration_test_generated_query() {
     let mut generator = SQLGenerator::new();
     let generated_query = generator.generate_sql(&ast);
 
-    let expected_query = "SELECT FROM (SELECT username AS username FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= 2020-01-01 AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < 2021-01-01 GROUP BY from_unixtime(fact_table.ts, 'YYYY-mm-dd'), fact_table.line_item_id, campaign_hierarchy.campaign_id) facts LEFT JOIN dim_campaign dim_campaign ON facts.campaign_id = dim_campaign.campaign_id";
+    let expected_query = "SELECT username AS username FROM fact_table fact_table LEFT JOIN campaign_hierarchy campaign_hierarchy ON fact_table.line_item_id = campaign_hierarchy.line_item_id WHERE from_unixtime(fact_table.ts, 'YYYY-mm-dd') >= DATE '2020-01-01' AND from_unixtime(fact_table.ts, 'YYYY-mm-dd') < DATE '2021-01-01' GROUP BY username";
     assert_eq!(generated_query.trim(), expected_query);
 }
 
 #[test]
 fn test_yaml_conversion() {
     let column: Column = Column {
-        name: Rc::from(""),
-        column_id: Rc::from(""),
-        expression: Rc::from(""),
+        name: Arc::from(""),
+        column_id: Arc::from(""),
+        expression: Arc::from(""),
         column_type: ColumnType::Aggregate,
-        data_type: Rc::from(""),
+        data_type: DataType::Text,
+        compatible_groupings: None,
     };
 
     let yml = serde_yml::to_string(&column).unwrap();
@@ -81,25 +112,40 @@ fn test_yaml_conversion() {
 #[test]
 fn integration_test_query_planner() {
     use reporting::executor::planner::QueryPlanner;
-    use reporting::domain::models::{Datasource, Column, ReportRequest, Filter, ColumnType};
-    use reporting::rc;
+    use reporting::domain::models::{Datasource, Column, DataType, ReportRequest, Filter, ColumnType, Schema};
+    use reporting::arc;
 
     // Setup a dummy column so that QueryPlanner.get_column can find it.
     let column = Column {
-        name: rc!["username"],
-        column_id: rc!["username"],
-        expression: rc!["username"],
+        name: arc!["username"],
+        column_id: arc!["username"],
+        expression: arc!["username"],
         column_type: ColumnType::Grouping,
-        data_type: rc!["text"],
+        data_type: DataType::Text,
+        compatible_groupings: None,
+    };
+    let date = Column {
+        name: arc!["date"],
+        column_id: arc!["date"],
+        expression: arc!["from_unixtime(fact_table.ts, 'YYYY-mm-dd')"],
+        column_type: ColumnType::Grouping,
+        data_type: DataType::Date,
+        compatible_groupings: None,
     };
 
     let datasource = Datasource {
-        name: rc!["default"],
-        columns: vec![column],
+        name: arc!["default"],
+        columns: vec![column, date],
+        timezone: None,
+        policies: vec![],
         // add other fields as needed
     };
 
-    let planner = QueryPlanner::new(datasource);
+    let schema: Schema =
+        load_yaml("test/schema.yaml").expect("Could not parse schema yaml");
+
+    let planner = QueryPlanner::new(datasource, schema)
+        .expect("Datasource should be valid");
     let request = ReportRequest {
         columns: vec!["username".to_string()],
         filters: Filter::And { value: vec![
@@ -107,6 +153,16 @@ fn integration_test_query_planner() {
             Filter::Lt { column: "date".to_string(), value: "2022-12-31".to_string() },
         ]},
         sort: vec![],
+        limit: None,
+        offset: None,
+        cursor: None,
+        granularity: None,
+        timezone: None,
+        compare: None,
+        totals: None,
+        top_n: None,
+        pivot: None,
+        datasource: None,
         // any additional fields required by ReportRequest
     };
 